@@ -0,0 +1,282 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::http_server;
+use crate::utils::constant_time_eq;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+const DEFAULT_PATH: &str = "/alertmanager";
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "ALERTMANAGER_PATH",
+            "HTTP path receiving Alertmanager webhook notifications (default: /alertmanager)",
+            false,
+        ),
+        ModuleParam::new(
+            "ALERTMANAGER_SECRET",
+            "Bearer token expected from Alertmanager (http_config.authorization.credentials)",
+            true,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for Alertmanager {
+    fn name(&self) -> &'static str {
+        "alertmanager"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        let mut lock = self.inner.inbox.lock().await;
+        if lock.is_empty() {
+            return None;
+        }
+        Some(lock.drain(..).collect())
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            triggers: Some(vec!["/alerts".to_string()]),
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(1)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        trace!("responding to /alerts");
+        let lock = self.inner.firing.lock().await;
+        if lock.is_empty() {
+            return Some(vec!["no firing alert".to_string()]);
+        }
+        let mut alerts = lock.values().collect::<Vec<&Alert>>();
+        alerts.sort_by(|a, b| a.starts_at.cmp(&b.starts_at));
+        let mut response = format!("{} firing alert(s):\n", alerts.len());
+        for alert in alerts {
+            response.push_str(&format!(
+                "- {} (since {})\n",
+                alert.describe(),
+                alert.starts_at
+            ));
+        }
+        Some(vec![response])
+    }
+
+    async fn send_message(&self, _messages: &[Message]) {}
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let lock = self.inner.firing.lock().await;
+        serde_json::to_value(&*lock).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        match serde_json::from_value::<HashMap<String, Alert>>(state) {
+            Ok(firing) => {
+                let mut lock = self.inner.firing.lock().await;
+                *lock = firing;
+            }
+            Err(err) => error!("cannot load alertmanager state: {}", err),
+        }
+    }
+}
+
+pub struct Alertmanager {
+    inner: Arc<AlertmanagerInner>,
+}
+
+impl Alertmanager {
+    pub fn new() -> Result<Self, String> {
+        let path = config::var("ALERTMANAGER_PATH").unwrap_or(DEFAULT_PATH.to_string());
+        let secret = config::var("ALERTMANAGER_SECRET")
+            .map_err(|err| format!("ALERTMANAGER_SECRET: {}", err))?;
+        let inner = Arc::new(AlertmanagerInner {
+            secret,
+            inbox: Mutex::new(Vec::new()),
+            firing: Mutex::new(HashMap::new()),
+        });
+        let handler_inner = inner.clone();
+        let handler = post(move |headers: HeaderMap, body: Bytes| async move {
+            handler_inner.receive(&headers, &body).await
+        });
+        http_server::route(&path, handler)?;
+        info!("alertmanager receiver on {} configured", path);
+        Ok(Alertmanager { inner })
+    }
+}
+
+struct AlertmanagerInner {
+    secret: String,
+    // Notifications received by the HTTP server and not yet sent
+    inbox: Mutex<Vec<Message>>,
+    // Firing alerts by fingerprint
+    firing: Mutex<HashMap<String, Alert>>,
+}
+
+impl AlertmanagerInner {
+    async fn receive(&self, headers: &HeaderMap, body: &[u8]) -> StatusCode {
+        let authorization = headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let expected = format!("Bearer {}", self.secret);
+        if !constant_time_eq(authorization.as_bytes(), expected.as_bytes()) {
+            warn!("alertmanager: unauthorized request");
+            return StatusCode::UNAUTHORIZED;
+        }
+        let notification: Notification = match serde_json::from_slice(body) {
+            Ok(notification) => notification,
+            Err(err) => {
+                warn!("alertmanager: malformed notification: {}", err);
+                return StatusCode::BAD_REQUEST;
+            }
+        };
+        trace!(
+            "alertmanager: {} notification with {} alert(s)",
+            notification.status,
+            notification.alerts.len()
+        );
+        {
+            let mut lock = self.firing.lock().await;
+            for alert in notification.alerts.iter() {
+                match alert.status.as_str() {
+                    "firing" => lock.insert(alert.key(), alert.clone()),
+                    _ => lock.remove(&alert.key()),
+                };
+            }
+        }
+        if let Some(message) = notification.message() {
+            self.inbox.lock().await.push(message);
+        }
+        StatusCode::OK
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Notification {
+    status: String,
+    #[serde(default)]
+    alerts: Vec<Alert>,
+    #[serde(default, rename = "groupLabels")]
+    group_labels: BTreeMap<String, String>,
+}
+
+impl Notification {
+    fn message(&self) -> Option<Message> {
+        let group = self
+            .group_labels
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut message = String::new();
+        for (status, title) in [("firing", "🔥 **FIRING**"), ("resolved", "✅ **RESOLVED**")] {
+            let alerts = self
+                .alerts
+                .iter()
+                .filter(|alert| alert.status == status)
+                .collect::<Vec<&Alert>>();
+            if alerts.is_empty() {
+                continue;
+            }
+            message.push_str(&format!("{} ({})", title, alerts.len()));
+            if !group.is_empty() {
+                message.push_str(&format!(" {}", group));
+            }
+            message.push('\n');
+            for alert in alerts {
+                message.push_str(&format!("- {}\n", alert.describe()));
+            }
+        }
+        if message.is_empty() {
+            return None;
+        }
+        Some(message)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Alert {
+    status: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+    #[serde(default, rename = "startsAt")]
+    starts_at: String,
+    #[serde(default, rename = "generatorURL")]
+    generator_url: String,
+    #[serde(default)]
+    fingerprint: String,
+}
+
+impl Alert {
+    // Old Alertmanager versions don't provide fingerprints, labels identify alerts
+    fn key(&self) -> String {
+        match self.fingerprint.is_empty() {
+            false => self.fingerprint.clone(),
+            true => format!("{:?}", self.labels),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let name = self
+            .labels
+            .get("alertname")
+            .map(String::as_str)
+            .unwrap_or("alert");
+        let mut description = match self.labels.get("severity") {
+            Some(severity) => format!("**{}** {}", severity, name),
+            None => format!("**{}**", name),
+        };
+        let labels = self
+            .labels
+            .iter()
+            .filter(|(label, _)| !matches!(label.as_str(), "alertname" | "severity"))
+            .map(|(label, value)| format!("{}={}", label, value))
+            .collect::<Vec<String>>();
+        if !labels.is_empty() {
+            description.push_str(&format!(" ({})", labels.join(", ")));
+        }
+        let summary = self
+            .annotations
+            .get("summary")
+            .or(self.annotations.get("description"));
+        if let Some(summary) = summary {
+            description.push_str(&format!(": {}", summary));
+        }
+        let runbook = self
+            .annotations
+            .get("runbook_url")
+            .or(self.annotations.get("runbook"));
+        if let Some(runbook) = runbook {
+            description.push_str(&format!(" [runbook]({})", runbook));
+        }
+        if !self.generator_url.is_empty() {
+            description.push_str(&format!(" [source]({})", self.generator_url));
+        }
+        description
+    }
+}
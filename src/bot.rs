@@ -1,22 +1,37 @@
-use crate::down_detectors::DownDetectors;
-use crate::feeds::Feeds;
-use crate::github_orgs::GithubOrgs;
-use crate::github_repos::GithubRepos;
-use crate::hello::Hello;
-use crate::help::Help;
-use crate::outscale_api_versions::OutscaleApiVersions;
-use crate::ping::Ping;
-use crate::roll::Roll;
-use crate::triggers::Triggers;
-use crate::webex::Webex;
-use crate::webpages::Webpages;
+use crate::alertmanager::{self, Alertmanager};
+use crate::certificates::{self, Certificates};
+use crate::config;
+use crate::discord::{self, Discord};
+use crate::down_detectors::{self, DownDetectors};
+use crate::feeds::{self, Feeds};
+use crate::github_activity::{self, GithubActivity};
+use crate::github_advisories::{self, GithubAdvisories};
+use crate::github_ci::{self, GithubCi};
+use crate::github_orgs::{self, GithubOrgs};
+use crate::github_repos::{self, GithubRepos};
+use crate::hello::{self, Hello};
+use crate::help::{self, Help};
+use crate::http_server::{self, HttpServer};
+use crate::matrix::{self, Matrix};
+use crate::mattermost::{self, Mattermost};
+use crate::metrics::{self, Metrics};
+use crate::outscale_api_versions::{self, OutscaleApiVersions};
+use crate::ping::{self, Ping};
+use crate::rocketchat::{self, RocketChat};
+use crate::roll::{self, Roll};
+use crate::slack::{self, Slack};
+use crate::state::{self, StateStore};
+use crate::triggers::{self, Triggers};
+use crate::webex::{self, Webex};
+use crate::webhook_in::{self, WebhookIn};
+use crate::webhook_out::{self, WebhookOut};
+use crate::webpages::{self, Webpages};
 use async_trait::async_trait;
 use log::{error, info, trace};
 use std::collections::HashMap;
-use std::env;
-use std::env::VarError;
+use std::fmt::Display;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::channel;
 use tokio::task::JoinSet;
 use tokio::time::sleep;
@@ -40,6 +55,7 @@ impl ModuleParam {
 
 pub type MessageResponse = String;
 pub type Message = String;
+pub type ModuleState = serde_json::Value;
 
 #[derive(Clone)]
 pub struct MessageCtx {
@@ -50,7 +66,6 @@ pub struct MessageCtx {
 #[async_trait]
 pub trait Module {
     fn name(&self) -> &'static str;
-    fn params(&self) -> Vec<ModuleParam>;
     fn variation_durations(&self) -> Vec<Duration>;
     fn capabilities(&self) -> ModuleCapabilities;
 
@@ -60,6 +75,8 @@ pub trait Module {
     async fn send_message(&self, messages: &[Message]);
     async fn read_message(&self) -> Option<Vec<MessageCtx>>;
     async fn resp_message(&self, parent: MessageCtx, message: Message);
+    async fn save_state(&self) -> Option<ModuleState>;
+    async fn load_state(&self, state: ModuleState);
 }
 
 pub type SharedModule = Arc<Box<dyn Module + Send + Sync>>;
@@ -79,7 +96,6 @@ pub struct ModuleData {
     pub module: SharedModule,
     pub name: &'static str,
     pub variation_durations: Vec<Duration>,
-    pub params: Vec<ModuleParam>,
     pub capabilities: ModuleCapabilities,
 }
 
@@ -87,14 +103,12 @@ impl ModuleData {
     fn new<M: Module + Send + Sync + 'static>(module: M) -> ModuleData {
         let name = module.name();
         let variation_durations = module.variation_durations();
-        let params = module.params();
         let capabilities = module.capabilities();
         let module: SharedModule = Arc::new(Box::new(module));
         ModuleData {
             module,
             name,
             variation_durations,
-            params,
             capabilities,
         }
     }
@@ -103,70 +117,160 @@ impl ModuleData {
 #[derive(Default)]
 pub struct Bot {
     modules: Vec<ModuleData>,
+    state: Option<Arc<StateStore>>,
+    known_params: Vec<(&'static str, Vec<ModuleParam>)>,
+    config_errors: Vec<String>,
+    http_server: Option<HttpServer>,
 }
 
 impl Bot {
     pub fn new() -> Self {
-        Bot::default()
-            .register("webex", Webex::new())
-            .register("ping", Ping::new())
-            .register("help", Help::new())
-            .register("down_detectors", DownDetectors::new())
-            .register("github_orgs", GithubOrgs::new())
-            .register("github_repos", GithubRepos::new())
-            .register("triggers", Triggers::new())
-            .register("hello", Hello::new())
-            .register("feeds", Feeds::new())
-            .register("roll", Roll::new())
-            .register("webpages", Webpages::new())
-            .register("outscale_api_versions", OutscaleApiVersions::new())
+        let mut bot = Bot {
+            known_params: vec![(
+                config::BOT_SECTION,
+                [state::params(), http_server::params()].concat(),
+            )],
+            ..Bot::default()
+        };
+        match StateStore::from_env() {
+            Ok(state) => bot.state = state.map(Arc::new),
+            Err(err) => bot
+                .config_errors
+                .push(format!("cannot init state store: {}", err)),
+        };
+        let mut bot = bot
+            .register("webex", webex::params(), Webex::new)
+            .register("slack", slack::params(), Slack::new)
+            .register("matrix", matrix::params(), Matrix::new)
+            .register("discord", discord::params(), Discord::new)
+            .register("mattermost", mattermost::params(), Mattermost::new)
+            .register("rocketchat", rocketchat::params(), RocketChat::new)
+            .register("webhook_out", webhook_out::params(), WebhookOut::new)
+            .register("webhook_in", webhook_in::params(), WebhookIn::new)
+            .register("alertmanager", alertmanager::params(), Alertmanager::new)
+            .register("metrics", metrics::params(), Metrics::new)
+            .register("ping", ping::params(), Ping::new)
+            .register("help", help::params(), Help::new)
+            .register(
+                "down_detectors",
+                down_detectors::params(),
+                DownDetectors::new,
+            )
+            .register("certificates", certificates::params(), Certificates::new)
+            .register("github_orgs", github_orgs::params(), GithubOrgs::new)
+            .register("github_repos", github_repos::params(), GithubRepos::new)
+            .register("github_ci", github_ci::params(), GithubCi::new)
+            .register(
+                "github_activity",
+                github_activity::params(),
+                GithubActivity::new,
+            )
+            .register(
+                "github_advisories",
+                github_advisories::params(),
+                GithubAdvisories::new,
+            )
+            .register("triggers", triggers::params(), Triggers::new)
+            .register("hello", hello::params(), Hello::new)
+            .register("feeds", feeds::params(), Feeds::new)
+            .register("roll", roll::params(), Roll::new)
+            .register("webpages", webpages::params(), Webpages::new)
+            .register(
+                "outscale_api_versions",
+                outscale_api_versions::params(),
+                OutscaleApiVersions::new,
+            );
+        let mut unknown_keys = config::unknown_keys(&bot.known_params);
+        bot.config_errors.append(&mut unknown_keys);
+        bot
     }
 
-    fn register<M: Module + Send + Sync + 'static>(
+    // Constructors return a displayable error, e.g. a missing variable or an invalid value
+    fn register<M: Module + Send + Sync + 'static, E: Display>(
         mut self,
-        module_name: &str,
-        module: Result<M, VarError>,
+        module_name: &'static str,
+        params: Vec<ModuleParam>,
+        module: fn() -> Result<M, E>,
     ) -> Self {
+        self.known_params.push((module_name, params.clone()));
         if !Bot::is_module_enabled(module_name) {
             info!("module {} is not enabled", module_name);
             return self;
         }
         info!("module {} is enabled", module_name);
-        let module = match module {
-            Ok(module) => module,
-            Err(err) => {
-                panic!("cannot init module {}: {}", module_name, err);
-            }
+        let missing_params = params
+            .iter()
+            .filter(|param| param.mandatory && config::var(&param.name).is_err())
+            .map(|param| param.name.clone())
+            .collect::<Vec<String>>();
+        if !missing_params.is_empty() {
+            self.config_errors.push(format!(
+                "module {}: missing mandatory parameter(s): {}",
+                module_name,
+                missing_params.join(", ")
+            ));
+            return self;
+        }
+        match module() {
+            Ok(module) => self.modules.push(ModuleData::new(module)),
+            Err(err) => self
+                .config_errors
+                .push(format!("cannot init module {}: {}", module_name, err)),
         };
-        self.modules.push(ModuleData::new(module));
         self
     }
 
+    pub fn config_errors(&self) -> &[String] {
+        &self.config_errors
+    }
+
+    pub async fn bind_http_server(&mut self) {
+        match http_server::bind().await {
+            Ok(http_server) => self.http_server = http_server,
+            Err(err) => self.config_errors.push(format!("http server: {}", err)),
+        }
+    }
+
     async fn send_modules(&self) {
         for module in self.modules.iter() {
             module.module.module_offering(&self.modules).await;
         }
     }
 
+    async fn load_states(&self) {
+        let Some(state) = self.state.as_ref() else {
+            return;
+        };
+        for module in self.modules.iter() {
+            if let Some(module_state) = state.load(module.name).await {
+                module.module.load_state(module_state).await;
+            }
+        }
+    }
+
     pub async fn help(&self) -> String {
         let mut output = String::new();
-        for module in self.modules.iter() {
-            output.push_str(format!("# '{}' module parameters\n", module.name).as_str());
-            output.push_str(
-                format!(
-                    "- BOT_MODULE_{}_ENABLED: enable module {} (optional: false)\n",
-                    module.name.to_uppercase(),
-                    module.name
-                )
-                .as_str(),
-            );
-            let param_map = module.params.iter().fold(
-                HashMap::<String, ModuleParam>::new(),
-                |mut map, param| {
-                    map.insert(param.name.clone(), param.clone());
-                    map
-                },
-            );
+        for (name, params) in self.known_params.iter() {
+            if *name == config::BOT_SECTION {
+                output.push_str("# bot parameters\n");
+            } else {
+                output.push_str(format!("# '{}' module parameters\n", name).as_str());
+                output.push_str(
+                    format!(
+                        "- {}: enable module {} (optional: false)\n",
+                        config::enabled_var(name),
+                        name
+                    )
+                    .as_str(),
+                );
+            }
+            let param_map =
+                params
+                    .iter()
+                    .fold(HashMap::<String, ModuleParam>::new(), |mut map, param| {
+                        map.insert(param.name.clone(), param.clone());
+                        map
+                    });
 
             for (param_name, param) in param_map.iter() {
                 output.push_str(
@@ -188,6 +292,7 @@ impl Bot {
             return;
         }
         let (mailbox_tx, mut mailbox_rx) = channel(100);
+        self.load_states().await;
         self.send_modules().await;
         let mut tasks = JoinSet::new();
         for module in self.modules.iter_mut() {
@@ -195,24 +300,49 @@ impl Bot {
                 let module = module.clone();
                 let duration = *duration;
                 let mailbox_tx = mailbox_tx.clone();
+                let state = self.state.clone();
                 tasks.spawn(async move {
                     let module = module.clone();
                     loop {
-                        if let Some(messages) = module.module.run(variation).await {
+                        let start = Instant::now();
+                        let messages = module.module.run(variation).await;
+                        metrics::module_run(
+                            module.name,
+                            variation,
+                            start.elapsed(),
+                            messages.as_ref().map_or(0, Vec::len),
+                        );
+                        if let Some(messages) = messages {
                             if let Err(err) = mailbox_tx.send(messages).await {
                                 error!("{}", err);
                             }
+                            metrics::mailbox_depth(
+                                mailbox_tx.max_capacity() - mailbox_tx.capacity(),
+                            );
+                        }
+                        if let Some(state) = state.as_ref() {
+                            if let Some(module_state) = module.module.save_state().await {
+                                state.save(module.name, module_state).await;
+                            }
                         }
                         sleep(duration).await;
                     }
                 });
             }
         }
+        if let Some(http_server) = self.http_server.take() {
+            tasks.spawn(async {
+                if let Err(err) = http_server.serve().await {
+                    error!("http server: {}", err);
+                }
+            });
+        }
         let modules = self.modules.clone();
         tasks.spawn(async move {
             let modules = modules;
             loop {
                 while let Some(messages) = mailbox_rx.recv().await {
+                    metrics::mailbox_depth(mailbox_rx.len());
                     for module in modules.iter() {
                         if module.capabilities.send_message {
                             module.module.send_message(&messages).await;
@@ -225,8 +355,8 @@ impl Bot {
     }
 
     fn is_module_enabled(module_name: &str) -> bool {
-        let env_var_name = format!("BOT_MODULE_{}_ENABLED", module_name.to_uppercase());
-        match env::var(&env_var_name) {
+        let env_var_name = config::enabled_var(module_name);
+        match config::var(&env_var_name) {
             Ok(env_var_value) => {
                 trace!(
                     "module {}: env {} is set to '{}'",
@@ -0,0 +1,519 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use simple_asn1::ASN1Block;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+
+const DEFAULT_PORT: u16 = 443;
+const DEFAULT_THRESHOLDS: [i64; 4] = [30, 14, 7, 1];
+const CONNECT_TIMEOUT_S: u64 = 10;
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "CERTIFICATES_0_TARGET",
+            "host:port or [ipv6]:port whose certificate is watched (port defaults to 443), can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "CERTIFICATES_0_SERVER_NAME",
+            "Name expected in the certificate if different from the target host (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "CERTIFICATES_THRESHOLDS",
+            "Warn this number of days before expiry, comma separated (default: 30,14,7,1)",
+            false,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for Certificates {
+    fn name(&self) -> &'static str {
+        "certificates"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        self.check_all_targets().await
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            triggers: Some(vec!["/certs".to_string()]),
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(3600)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        trace!("responding to /certs");
+        let targets = self.targets.read().await;
+        if targets.is_empty() {
+            return Some(vec!["no certificate watched".to_string()]);
+        }
+        let mut sorted = targets.iter().collect::<Vec<&CertTarget>>();
+        // Unknown expiries last
+        sorted.sort_by_key(|target| target.state.not_after.unwrap_or(i64::MAX));
+        let mut response = String::from("certificate expiries:\n");
+        for target in sorted {
+            response.push_str(&format!("- {}\n", target.describe()));
+        }
+        Some(vec![response])
+    }
+
+    async fn send_message(&self, _messages: &[Message]) {}
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let targets = self.targets.read().await;
+        let states = targets
+            .iter()
+            .map(|target| (target.target.clone(), target.state.clone()))
+            .collect::<BTreeMap<String, CertState>>();
+        serde_json::to_value(states).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let mut states: BTreeMap<String, CertState> = match serde_json::from_value(state) {
+            Ok(states) => states,
+            Err(err) => {
+                error!("cannot load certificates state: {}", err);
+                return;
+            }
+        };
+        let mut targets = self.targets.write().await;
+        for target in targets.iter_mut() {
+            if let Some(state) = states.remove(&target.target) {
+                target.state = state;
+            }
+        }
+    }
+}
+
+pub struct Certificates {
+    thresholds: Vec<i64>,
+    verifier: Arc<WebPkiServerVerifier>,
+    connector: TlsConnector,
+    targets: RwLock<Vec<CertTarget>>,
+}
+
+impl Certificates {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut targets = Vec::new();
+        for i in 0..100 {
+            let Ok(target) = config::var(format!("CERTIFICATES_{}_TARGET", i)) else {
+                break;
+            };
+            let server_name = config::var(format!("CERTIFICATES_{}_SERVER_NAME", i)).ok();
+            info!("certificate watch on {} configured", target);
+            targets.push(CertTarget::new(target, server_name)?);
+        }
+        if targets.is_empty() {
+            warn!("certificates module enabled but no target configured");
+        }
+        let thresholds = match config::var("CERTIFICATES_THRESHOLDS") {
+            Ok(thresholds) => thresholds
+                .split(',')
+                .map(|days| match days.trim().parse::<i64>() {
+                    Ok(days) if days >= 0 => Ok(days),
+                    _ => Err(format!(
+                        "CERTIFICATES_THRESHOLDS: invalid number of days '{}'",
+                        days.trim()
+                    )),
+                })
+                .collect::<Result<Vec<i64>, _>>()?,
+            Err(_) => DEFAULT_THRESHOLDS.to_vec(),
+        };
+
+        let provider = Arc::new(crypto::aws_lc_rs::default_provider());
+        let mut roots = RootCertStore::empty();
+        let native_certs = rustls_native_certs::load_native_certs();
+        for err in native_certs.errors.iter() {
+            warn!("certificates: cannot load native root certificate: {}", err);
+        }
+        let (added, ignored) = roots.add_parsable_certificates(native_certs.certs);
+        trace!(
+            "certificates: {} root certificates loaded, {} ignored",
+            added,
+            ignored
+        );
+        let verifier =
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|err| format!("cannot build certificate verifier: {}", err))?;
+        // Certificates are only collected during the handshake, they are verified afterwards
+        let config = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|err| format!("cannot build TLS configuration: {}", err))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(CollectingVerifier { provider }))
+            .with_no_client_auth();
+
+        Ok(Certificates {
+            thresholds,
+            verifier,
+            connector: TlsConnector::from(Arc::new(config)),
+            targets: RwLock::new(targets),
+        })
+    }
+
+    async fn check_all_targets(&self) -> Option<Vec<Message>> {
+        let mut messages = Vec::new();
+        let mut targets = self.targets.write().await;
+        for target in targets.iter_mut() {
+            trace!("certificates: checking {}", target.target);
+            match target.fetch(&self.connector, &self.verifier).await {
+                Ok(cert) => messages.append(&mut target.update(cert, &self.thresholds)),
+                Err(err) => {
+                    error!("certificates: cannot check {}: {}", target.target, err);
+                    target.state.last_error = Some(err.to_string());
+                }
+            }
+        }
+        if messages.is_empty() {
+            return None;
+        }
+        Some(messages)
+    }
+}
+
+// Accepts host, host:port, ip, ip:port and [ipv6]:port
+fn parse_target(target: &str) -> Result<(String, u16), String> {
+    if let Ok(address) = target.parse::<SocketAddr>() {
+        return Ok((address.ip().to_string(), address.port()));
+    }
+    if let Ok(ip) = target
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        return Ok((ip.to_string(), DEFAULT_PORT));
+    }
+    match target.rsplit_once(':') {
+        Some((host, _)) if host.contains(':') => Err(format!("invalid target {}", target)),
+        Some((host, port)) => match port.parse() {
+            Ok(port) => Ok((host.to_string(), port)),
+            Err(err) => Err(format!("invalid port in {}: {}", target, err)),
+        },
+        None => Ok((target.to_string(), DEFAULT_PORT)),
+    }
+}
+
+struct CertTarget {
+    // host:port as configured
+    target: String,
+    host: String,
+    port: u16,
+    server_name: ServerName<'static>,
+    state: CertState,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct CertState {
+    // Expiry timestamp of the last seen certificate
+    not_after: Option<i64>,
+    // Smallest threshold (in days) already warned for this certificate, 0 once expired
+    warned: Option<i64>,
+    problems: BTreeSet<String>,
+    #[serde(skip)]
+    last_error: Option<String>,
+}
+
+struct PeerCert {
+    not_after: i64,
+    problems: BTreeSet<String>,
+}
+
+impl CertTarget {
+    fn new(target: String, server_name: Option<String>) -> Result<Self, String> {
+        let (host, port) = parse_target(&target)?;
+        let name = server_name.unwrap_or(host.clone());
+        let server_name = ServerName::try_from(name.clone())
+            .map_err(|err| format!("invalid server name {}: {}", name, err))?;
+        Ok(CertTarget {
+            target,
+            host,
+            port,
+            server_name,
+            state: CertState::default(),
+        })
+    }
+
+    async fn fetch(
+        &self,
+        connector: &TlsConnector,
+        verifier: &WebPkiServerVerifier,
+    ) -> Result<PeerCert, Box<dyn Error + Send + Sync>> {
+        let connect = async {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            connector.connect(self.server_name.clone(), stream).await
+        };
+        let stream = timeout(Duration::from_secs(CONNECT_TIMEOUT_S), connect).await??;
+        let chain = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .ok_or("no certificate provided")?
+            .to_vec();
+        let (end_entity, intermediates) = chain.split_first().ok_or("empty certificate chain")?;
+        let not_after = not_after(end_entity)?;
+
+        let mut problems = BTreeSet::new();
+        let end_entity_cert = webpki::EndEntityCert::try_from(end_entity)?;
+        if end_entity_cert
+            .verify_is_valid_for_subject_name(&self.server_name)
+            .is_err()
+        {
+            problems.insert("hostname mismatch".to_string());
+        }
+        // Expiry is reported separately, verify the chain while the certificate is still valid
+        let now = Utc::now().timestamp().min(not_after - 1).max(0) as u64;
+        let verified = verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            &self.server_name,
+            &[],
+            UnixTime::since_unix_epoch(Duration::from_secs(now)),
+        );
+        match verified {
+            Ok(_) => {}
+            Err(tokio_rustls::rustls::Error::InvalidCertificate(err)) => match err {
+                CertificateError::NotValidForName
+                | CertificateError::NotValidForNameContext { .. } => {}
+                CertificateError::UnknownIssuer => {
+                    problems.insert("incomplete or untrusted chain".to_string());
+                }
+                err => {
+                    problems.insert(format!("invalid chain ({:?})", err));
+                }
+            },
+            Err(err) => {
+                problems.insert(format!("invalid chain ({})", err));
+            }
+        };
+        Ok(PeerCert {
+            not_after,
+            problems,
+        })
+    }
+
+    fn update(&mut self, cert: PeerCert, thresholds: &[i64]) -> Vec<Message> {
+        let mut messages = Vec::new();
+        let state = &mut self.state;
+        state.last_error = None;
+        if state.not_after.is_some_and(|old| cert.not_after > old) {
+            if state.warned.is_some() {
+                messages.push(format!(
+                    "✅ certificate of {} has been renewed, it now expires on {}",
+                    self.target,
+                    format_date(cert.not_after)
+                ));
+            }
+            state.warned = None;
+        }
+        state.not_after = Some(cert.not_after);
+
+        let now = Utc::now().timestamp();
+        let days_left = (cert.not_after - now).div_euclid(24 * 3600);
+        let level = match cert.not_after <= now {
+            true => Some(0),
+            false => thresholds
+                .iter()
+                .filter(|threshold| days_left <= **threshold)
+                .min()
+                .copied(),
+        };
+        if let Some(level) = level {
+            if state.warned.is_none_or(|warned| level < warned) {
+                state.warned = Some(level);
+                messages.push(match level {
+                    0 => format!(
+                        "🔴 certificate of {} expired on {}",
+                        self.target,
+                        format_date(cert.not_after)
+                    ),
+                    _ => format!(
+                        "⚠️ certificate of {} expires in {} day(s) on {}",
+                        self.target,
+                        days_left,
+                        format_date(cert.not_after)
+                    ),
+                });
+            }
+        }
+
+        for problem in cert.problems.difference(&state.problems) {
+            messages.push(format!("❗ certificate of {}: {}", self.target, problem));
+        }
+        for problem in state.problems.difference(&cert.problems) {
+            messages.push(format!(
+                "✅ certificate of {}: {} is fixed",
+                self.target, problem
+            ));
+        }
+        state.problems = cert.problems;
+        messages
+    }
+
+    fn describe(&self) -> String {
+        let mut description = match self.state.not_after {
+            Some(not_after) => {
+                let days_left = (not_after - Utc::now().timestamp()).div_euclid(24 * 3600);
+                format!(
+                    "{}: expires on {} ({} days)",
+                    self.target,
+                    format_date(not_after),
+                    days_left
+                )
+            }
+            None => format!("{}: unknown expiry", self.target),
+        };
+        for problem in self.state.problems.iter() {
+            description.push_str(&format!(", {}", problem));
+        }
+        if let Some(err) = self.state.last_error.as_ref() {
+            description.push_str(&format!(" (last check failed: {})", err));
+        }
+        description
+    }
+}
+
+fn format_date(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or(timestamp.to_string())
+}
+
+// Reads notAfter from the validity sequence of the TBSCertificate
+fn not_after(cert: &CertificateDer) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    let blocks = simple_asn1::from_der(cert.as_ref())?;
+    let Some(ASN1Block::Sequence(_, certificate)) = blocks.first() else {
+        return Err("certificate is not a sequence".into());
+    };
+    let Some(ASN1Block::Sequence(_, tbs)) = certificate.first() else {
+        return Err("TBSCertificate is not a sequence".into());
+    };
+    for block in tbs {
+        let ASN1Block::Sequence(_, validity) = block else {
+            continue;
+        };
+        match validity.as_slice() {
+            [_, ASN1Block::UTCTime(_, date) | ASN1Block::GeneralizedTime(_, date)] => {
+                return Ok(date.assume_utc().unix_timestamp());
+            }
+            _ => continue,
+        }
+    }
+    Err("certificate validity not found".into())
+}
+
+// Accepts any certificate so expired or mismatching ones can still be inspected
+#[derive(Debug)]
+struct CollectingVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for CollectingVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_targets() {
+        let target = |target: &str| parse_target(target).unwrap();
+        assert_eq!(target("example.com"), ("example.com".to_string(), 443));
+        assert_eq!(
+            target("example.com:8443"),
+            ("example.com".to_string(), 8443)
+        );
+        assert_eq!(target("192.0.2.1"), ("192.0.2.1".to_string(), 443));
+        assert_eq!(target("192.0.2.1:8443"), ("192.0.2.1".to_string(), 8443));
+        assert_eq!(target("2001:db8::1"), ("2001:db8::1".to_string(), 443));
+        assert_eq!(target("[2001:db8::1]"), ("2001:db8::1".to_string(), 443));
+        assert_eq!(
+            target("[2001:db8::1]:8443"),
+            ("2001:db8::1".to_string(), 8443)
+        );
+        assert!(parse_target("example.com:https").is_err());
+        assert!(parse_target("[2001:db8::1]:https").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::bot::ModuleParam;
+use log::{info, trace};
+use regex::Regex;
+use std::collections::HashMap;
+use std::env::{self, VarError};
+use std::error::Error;
+use std::fs;
+use std::sync::OnceLock;
+use toml::{Table, Value};
+
+// Section holding bot-wide parameters which are not related to a module
+pub const BOT_SECTION: &str = "bot";
+
+static CONFIG_FILE: OnceLock<ConfigFile> = OnceLock::new();
+
+// Environment variables always override values provided in the configuration file
+pub fn var<K: AsRef<str>>(name: K) -> Result<String, VarError> {
+    let name = name.as_ref();
+    match env::var(name) {
+        Ok(value) => Ok(value),
+        Err(VarError::NotPresent) => CONFIG_FILE
+            .get()
+            .and_then(|config| config.values.get(name).cloned())
+            .ok_or(VarError::NotPresent),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn load_file(path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let content = fs::read_to_string(path)?;
+    let config = ConfigFile::parse(&content)?;
+    info!("configuration file {} loaded", path);
+    CONFIG_FILE
+        .set(config)
+        .map_err(|_| "configuration file already loaded".into())
+}
+
+pub fn unknown_keys(known_params: &[(&str, Vec<ModuleParam>)]) -> Vec<String> {
+    let Some(config) = CONFIG_FILE.get() else {
+        return Vec::new();
+    };
+    let mut errors = Vec::new();
+    for (section, keys) in config.sections.iter() {
+        let Some((_, params)) = known_params.iter().find(|(name, _)| name == section) else {
+            errors.push(format!("configuration file: unknown section [{}]", section));
+            continue;
+        };
+        let patterns = params
+            .iter()
+            .filter_map(|param| param_pattern(&param.name))
+            .collect::<Vec<Regex>>();
+        for key in keys {
+            if *key == enabled_var(section) {
+                continue;
+            }
+            if !patterns.iter().any(|pattern| pattern.is_match(key)) {
+                errors.push(format!(
+                    "configuration file: unknown parameter {} in section [{}]",
+                    key, section
+                ));
+            }
+        }
+    }
+    errors
+}
+
+pub fn enabled_var(module_name: &str) -> String {
+    format!("BOT_MODULE_{}_ENABLED", module_name.to_uppercase())
+}
+
+// Indexed parameters are documented with index 0 (e.g. FEED_0_NAME) but can be repeated
+fn param_pattern(param_name: &str) -> Option<Regex> {
+    let pattern = regex::escape(param_name)
+        .split('_')
+        .map(|part| if part == "0" { r"\d+" } else { part })
+        .collect::<Vec<&str>>()
+        .join("_");
+    Regex::new(format!("^{}$", pattern).as_str()).ok()
+}
+
+#[derive(Default)]
+struct ConfigFile {
+    // Flattened parameter name -> value
+    values: HashMap<String, String>,
+    // Section name -> flattened parameter names
+    sections: HashMap<String, Vec<String>>,
+}
+
+impl ConfigFile {
+    // Each section is named after a module and each key is converted to its environment
+    // variable name:
+    //
+    // [feeds]
+    // enabled = true                                  -> BOT_MODULE_FEEDS_ENABLED
+    // feed = [{ name = "blog", url = "https://..." }] -> FEED_0_NAME, FEED_0_URL
+    fn parse(content: &str) -> Result<ConfigFile, Box<dyn Error + Send + Sync>> {
+        let table: Table = content.parse()?;
+        let mut config = ConfigFile::default();
+        for (section, value) in table {
+            let Value::Table(section_table) = value else {
+                return Err(format!("configuration file: '{}' must be a section", section).into());
+            };
+            let mut keys = Vec::new();
+            for (key, value) in section_table {
+                let name = match key.as_str() {
+                    "enabled" if section != BOT_SECTION => enabled_var(&section),
+                    _ => key.to_uppercase(),
+                };
+                for (name, value) in flatten(&name, value)? {
+                    trace!("configuration file: [{}] {} is set", section, name);
+                    config.values.insert(name.clone(), value);
+                    keys.push(name);
+                }
+            }
+            config.sections.insert(section, keys);
+        }
+        Ok(config)
+    }
+}
+
+fn flatten(
+    name: &str,
+    value: Value,
+) -> Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>> {
+    let value = match value {
+        Value::String(value) => value,
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Array(items) => {
+            let mut values = Vec::new();
+            for (i, item) in items.into_iter().enumerate() {
+                let Value::Table(item) = item else {
+                    return Err(
+                        format!("configuration file: items of {} must be tables", name).into(),
+                    );
+                };
+                for (key, value) in item {
+                    let item_name = format!("{}_{}_{}", name, i, key.to_uppercase());
+                    values.append(&mut flatten(&item_name, value)?);
+                }
+            }
+            return Ok(values);
+        }
+        _ => return Err(format!("configuration file: unsupported value for {}", name).into()),
+    };
+    Ok(vec![(name.to_string(), value)])
+}
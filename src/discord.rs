@@ -0,0 +1,281 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::metrics;
+use crate::utils::{request_agent, MeasuredSend};
+use async_trait::async_trait;
+use log::{error, trace};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use std::env::VarError;
+use std::error::Error;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+const DEFAULT_DISCORD_API_URL: &str = "https://discord.com/api/v10";
+// Discord refuses messages longer than this
+const MAX_MESSAGE_LEN: usize = 2000;
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new("DISCORD_TOKEN", "Discord bot token. See how to create a [bot account](https://discord.com/developers/docs/quick-start/getting-started).", true),
+        ModuleParam::new("DISCORD_CHANNEL_ID", "Discord channel id where to speak", true),
+        ModuleParam::new(
+            "DISCORD_API_URL",
+            "Discord API base URL (default: https://discord.com/api/v10)",
+            false,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for Discord {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        None
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            send_message: true,
+            read_message: true,
+            resp_message: true,
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(2)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, messages: &[Message]) {
+        for message in messages {
+            self.agent.say(message, None).await;
+        }
+    }
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        let messages = match self.agent.unread_mentions().await {
+            Ok(messages) => {
+                metrics::chat_backend_reachable(self.name(), true);
+                messages
+            }
+            Err(err) => {
+                metrics::chat_backend_reachable(self.name(), false);
+                error!("discord: cannot read messages: {}", err);
+                return None;
+            }
+        };
+        let unread_messages = messages
+            .into_iter()
+            .map(|message| MessageCtx {
+                content: message.content,
+                id: message.id,
+            })
+            .collect::<Vec<MessageCtx>>();
+        if unread_messages.is_empty() {
+            return None;
+        }
+        Some(unread_messages)
+    }
+
+    async fn resp_message(&self, parent: MessageCtx, message: Message) {
+        trace!(
+            "richard responding to discord message {}: {}",
+            parent.id,
+            message
+        );
+        self.agent.say(&message, Some(parent.id)).await;
+    }
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let lock = self.agent.last_message_id.lock().await;
+        let last_message_id = lock.as_ref()?;
+        serde_json::to_value(last_message_id).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        match serde_json::from_value::<String>(state) {
+            Ok(id) => {
+                let mut lock = self.agent.last_message_id.lock().await;
+                *lock = Some(id);
+            }
+            Err(err) => error!("cannot load discord state: {}", err),
+        }
+    }
+}
+
+pub struct Discord {
+    agent: DiscordAgent,
+}
+
+impl Discord {
+    pub fn new() -> Result<Self, VarError> {
+        Ok(Discord {
+            agent: DiscordAgent::new()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct DiscordAgent {
+    api_url: String,
+    auth_header: String,
+    channel_id: String,
+    bot_user_id: Mutex<Option<String>>,
+    last_message_id: Mutex<Option<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Default)]
+struct DiscordCreateMessage {
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_reference: Option<DiscordMessageReference>,
+}
+
+#[derive(Clone, Debug, Serialize, Default)]
+struct DiscordMessageReference {
+    message_id: String,
+}
+
+impl DiscordAgent {
+    fn new() -> Result<DiscordAgent, VarError> {
+        let token = config::var("DISCORD_TOKEN")?;
+        let channel_id = config::var("DISCORD_CHANNEL_ID")?;
+        let api_url = config::var("DISCORD_API_URL").unwrap_or(DEFAULT_DISCORD_API_URL.to_string());
+        Ok(DiscordAgent {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            auth_header: format!("Bot {}", token),
+            channel_id,
+            bot_user_id: Mutex::new(None),
+            last_message_id: Mutex::new(None),
+        })
+    }
+
+    fn get<T: Into<String>>(&self, url: T) -> Result<RequestBuilder, Box<dyn Error + Send + Sync>> {
+        Ok(request_agent()?
+            .get(url.into())
+            .header("Authorization", &self.auth_header))
+    }
+
+    async fn say(&self, message: &str, reply_to: Option<String>) {
+        // Discord already renders markdown, only the length needs care
+        // Messages over the limit are cut one character earlier to fit the ellipsis
+        let content = match message.char_indices().nth(MAX_MESSAGE_LEN - 1) {
+            Some((index, _)) if message.chars().count() > MAX_MESSAGE_LEN => {
+                format!("{}…", &message[..index])
+            }
+            _ => message.to_string(),
+        };
+        let request = DiscordCreateMessage {
+            content,
+            message_reference: reply_to.map(|message_id| DiscordMessageReference { message_id }),
+        };
+        let Ok(agent) = request_agent() else {
+            error!("cannot get request agent");
+            return;
+        };
+        match agent
+            .post(format!(
+                "{}/channels/{}/messages",
+                self.api_url, self.channel_id
+            ))
+            .header("Authorization", &self.auth_header)
+            .json(&request)
+            .send_measured()
+            .await
+        {
+            Ok(resp) if !resp.status().is_success() => error!(
+                "discord post: status: {}, content: {:#?}",
+                resp.status(),
+                resp.text().await
+            ),
+            Ok(resp) => trace!("discord post: status: {}", resp.status()),
+            Err(err) => error!("discord post: {}", err),
+        };
+    }
+
+    async fn bot_user_id(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut lock = self.bot_user_id.lock().await;
+        if let Some(user_id) = lock.as_ref() {
+            return Ok(user_id.clone());
+        }
+        let user: DiscordUser = self
+            .get(format!("{}/users/@me", self.api_url))?
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        trace!("discord bot user id is {}", user.id);
+        *lock = Some(user.id.clone());
+        Ok(user.id)
+    }
+
+    async fn unread_mentions(&self) -> Result<Vec<DiscordMessage>, Box<dyn Error + Send + Sync>> {
+        let bot_user_id = self.bot_user_id().await?;
+        let mut lock = self.last_message_id.lock().await;
+        let mut url = format!(
+            "{}/channels/{}/messages?limit=100",
+            self.api_url, self.channel_id
+        );
+        if let Some(ref last) = *lock {
+            url.push_str(format!("&after={}", last).as_str());
+        }
+        let body = self
+            .get(url)?
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        trace!("{}", body);
+        let mut messages: Vec<DiscordMessage> = serde_json::from_str(body.as_str())?;
+
+        // Discord lists newest messages first
+        messages.reverse();
+        let first_read = lock.is_none();
+        if let Some(m) = messages.last() {
+            *lock = Some(m.id.clone());
+        } else if first_read {
+            *lock = Some(String::from("0"));
+        }
+        // Don't answer to messages sent before the bot started for the first time
+        if first_read {
+            return Ok(Vec::new());
+        }
+        // Bots only get the content of messages mentioning them without the privileged
+        // message content intent
+        messages.retain(|m| !m.author.bot && m.mentions.iter().any(|user| user.id == bot_user_id));
+        Ok(messages)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct DiscordUser {
+    id: String,
+    #[serde(default)]
+    bot: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct DiscordMessage {
+    id: String,
+    #[serde(default)]
+    content: String,
+    author: DiscordUser,
+    #[serde(default)]
+    mentions: Vec<DiscordUser>,
+}
@@ -0,0 +1,517 @@
+// Minimal DNS client (RFC 1035) sending a single question over UDP to a given resolver,
+// truncated answers are retried over TCP
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{timeout, Duration};
+
+const DNS_PORT: u16 = 53;
+const MAX_PACKET_SIZE: usize = 4096;
+const HEADER_SIZE: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Mx,
+    Ns,
+    Txt,
+}
+
+impl RecordType {
+    fn code(&self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+        }
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "A" => Ok(RecordType::A),
+            "AAAA" => Ok(RecordType::Aaaa),
+            "CNAME" => Ok(RecordType::Cname),
+            "MX" => Ok(RecordType::Mx),
+            "NS" => Ok(RecordType::Ns),
+            "TXT" => Ok(RecordType::Txt),
+            other => Err(format!("unsupported record type {}", other)),
+        }
+    }
+}
+
+impl Display for RecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RecordType::A => "A",
+            RecordType::Aaaa => "AAAA",
+            RecordType::Cname => "CNAME",
+            RecordType::Mx => "MX",
+            RecordType::Ns => "NS",
+            RecordType::Txt => "TXT",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Accepts "ip" or "ip:port", e.g. 1.1.1.1 or [2606:4700:4700::1111]:53
+pub fn parse_resolver(resolver: &str) -> Option<SocketAddr> {
+    if let Ok(address) = resolver.parse::<SocketAddr>() {
+        return Some(address);
+    }
+    let ip = resolver.parse::<IpAddr>().ok()?;
+    Some(SocketAddr::new(ip, DNS_PORT))
+}
+
+// First nameserver of /etc/resolv.conf
+pub fn system_resolver() -> Option<SocketAddr> {
+    let resolv_conf = fs::read_to_string("/etc/resolv.conf").ok()?;
+    resolv_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|resolver| parse_resolver(resolver.trim()))
+}
+
+// Returns the records of the requested type, names are returned without trailing dot
+pub async fn resolve(
+    resolver: SocketAddr,
+    name: &str,
+    record_type: RecordType,
+    max_duration: Duration,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let query = build_query(rand::random::<u16>(), name, record_type)?;
+    let response = timeout(max_duration, exchange(resolver, &query))
+        .await
+        .map_err(|_| format!("no response from {} after {:?}", resolver, max_duration))??;
+    parse_response(&response, &query, record_type)
+}
+
+async fn exchange(
+    resolver: SocketAddr,
+    query: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let response = exchange_udp(resolver, query).await?;
+    if read_u16(&response, 2)? & FLAG_TRUNCATED == 0 {
+        return Ok(response);
+    }
+    exchange_tcp(resolver, query).await
+}
+
+async fn exchange_udp(
+    resolver: SocketAddr,
+    query: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let bind_address: SocketAddr = match resolver {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_address).await?;
+    socket.connect(resolver).await?;
+    socket.send(query).await?;
+    let mut response = vec![0; MAX_PACKET_SIZE];
+    loop {
+        let size = socket.recv(&mut response).await?;
+        // Ignore late answers of previous queries and spoofed answers
+        if check_query(&response[..size], query).is_ok() {
+            response.truncate(size);
+            return Ok(response);
+        }
+    }
+}
+
+// Messages are prefixed by their length over TCP (RFC 1035 4.2.2)
+async fn exchange_tcp(
+    resolver: SocketAddr,
+    query: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut stream = TcpStream::connect(resolver).await?;
+    let mut message = (query.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(query);
+    stream.write_all(&message).await?;
+    let length = stream.read_u16().await?;
+    let mut response = vec![0; length as usize];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+fn build_query(
+    id: u16,
+    name: &str,
+    record_type: RecordType,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut query = Vec::with_capacity(HEADER_SIZE + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Standard query with recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid name {}", name).into());
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.code().to_be_bytes());
+    // Class IN
+    query.extend_from_slice(&1u16.to_be_bytes());
+    Ok(query)
+}
+
+// The response must answer the single question of the query with the same transaction id
+fn check_query(packet: &[u8], query: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if read_u16(packet, 0)? != read_u16(query, 0)? {
+        return Err("transaction id mismatch".into());
+    }
+    if read_u16(packet, 2)? & FLAG_RESPONSE == 0 {
+        return Err("not a response".into());
+    }
+    // Names are case insensitive and servers may change the case of the question
+    let question = &query[HEADER_SIZE..];
+    let answered_question = packet
+        .get(HEADER_SIZE..HEADER_SIZE + question.len())
+        .ok_or("truncated question")?;
+    if read_u16(packet, 4)? != 1 || !answered_question.eq_ignore_ascii_case(question) {
+        return Err("question mismatch".into());
+    }
+    Ok(())
+}
+
+fn parse_response(
+    packet: &[u8],
+    query: &[u8],
+    record_type: RecordType,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    check_query(packet, query)?;
+    let flags = read_u16(packet, 2)?;
+    if flags & FLAG_TRUNCATED != 0 {
+        return Err("truncated response".into());
+    }
+    match flags & 0x000f {
+        0 => {}
+        2 => return Err("server failure".into()),
+        3 => return Err("name does not exist".into()),
+        5 => return Err("query refused".into()),
+        rcode => return Err(format!("error code {}", rcode).into()),
+    };
+    let answers = read_u16(packet, 6)?;
+    let mut offset = query.len();
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(packet, offset)?;
+        let answer_type = read_u16(packet, offset)?;
+        let data_length = read_u16(packet, offset + 8)? as usize;
+        let data_offset = offset + 10;
+        let data = packet
+            .get(data_offset..data_offset + data_length)
+            .ok_or("truncated record")?;
+        offset = data_offset + data_length;
+        // Answers may also contain the CNAME chain leading to the requested records
+        if answer_type != record_type.code() {
+            continue;
+        }
+        let record = match record_type {
+            RecordType::A => {
+                let octets: [u8; 4] = data.try_into().map_err(|_| "invalid A record")?;
+                Ipv4Addr::from(octets).to_string()
+            }
+            RecordType::Aaaa => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| "invalid AAAA record")?;
+                Ipv6Addr::from(octets).to_string()
+            }
+            RecordType::Cname | RecordType::Ns => read_name(packet, data_offset)?,
+            // Preference is ignored, only the exchange name is kept
+            RecordType::Mx => read_name(packet, data_offset + 2)?,
+            RecordType::Txt => {
+                let mut text = String::new();
+                let mut position = 0;
+                while let Some(length) = data.get(position) {
+                    let part = data
+                        .get(position + 1..position + 1 + *length as usize)
+                        .ok_or("invalid TXT record")?;
+                    text.push_str(&String::from_utf8_lossy(part));
+                    position += 1 + *length as usize;
+                }
+                text
+            }
+        };
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> Result<u16, Box<dyn Error + Send + Sync>> {
+    let bytes = packet.get(offset..offset + 2).ok_or("truncated packet")?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// Returns the offset following the name
+fn skip_name(packet: &[u8], mut offset: usize) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    loop {
+        let length = *packet.get(offset).ok_or("truncated name")?;
+        match length {
+            0 => return Ok(offset + 1),
+            // Compression pointer
+            length if length & 0xc0 == 0xc0 => return Ok(offset + 2),
+            length => offset += 1 + length as usize,
+        }
+    }
+}
+
+fn read_name(packet: &[u8], mut offset: usize) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut labels = Vec::new();
+    // Bound pointer jumps to avoid loops in malformed packets
+    for _ in 0..128 {
+        let length = *packet.get(offset).ok_or("truncated name")?;
+        match length {
+            0 => return Ok(labels.join(".")),
+            length if length & 0xc0 == 0xc0 => {
+                offset = (read_u16(packet, offset)? & 0x3fff) as usize;
+            }
+            length => {
+                let label = packet
+                    .get(offset + 1..offset + 1 + length as usize)
+                    .ok_or("truncated label")?;
+                labels.push(String::from_utf8_lossy(label).to_string());
+                offset += 1 + length as usize;
+            }
+        }
+    }
+    Err("too many labels in name".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY_ID: u16 = 0x1234;
+    // Offset of the question name, used as compression pointer target
+    const NAME_POINTER: [u8; 2] = [0xc0, 0x0c];
+
+    fn example_query(record_type: RecordType) -> Vec<u8> {
+        build_query(QUERY_ID, "example.com", record_type).unwrap()
+    }
+
+    // Answers are (type, data) and their name points to the question name
+    fn response(query: &[u8], flags: u16, answers: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut packet = query[..2].to_vec();
+        packet.extend_from_slice(&flags.to_be_bytes());
+        packet.extend_from_slice(&[0, 1]);
+        packet.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend_from_slice(&query[HEADER_SIZE..]);
+        for (answer_type, data) in answers {
+            packet.extend_from_slice(&NAME_POINTER);
+            packet.extend_from_slice(&answer_type.to_be_bytes());
+            packet.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+            packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            packet.extend_from_slice(data);
+        }
+        packet
+    }
+
+    fn parse(record_type: RecordType, answers: &[(u16, Vec<u8>)]) -> Vec<String> {
+        let query = example_query(record_type);
+        let packet = response(&query, 0x8180, answers);
+        parse_response(&packet, &query, record_type).unwrap()
+    }
+
+    // "www" followed by a pointer to the question name
+    fn www_name() -> Vec<u8> {
+        let mut name = vec![3, b'w', b'w', b'w'];
+        name.extend_from_slice(&NAME_POINTER);
+        name
+    }
+
+    #[test]
+    fn parse_a() {
+        let records = parse(
+            RecordType::A,
+            &[(1, vec![192, 0, 2, 1]), (1, vec![192, 0, 2, 2])],
+        );
+        assert_eq!(records, vec!["192.0.2.1", "192.0.2.2"]);
+    }
+
+    #[test]
+    fn parse_aaaa() {
+        let mut data = vec![0x20, 0x01, 0x0d, 0xb8];
+        data.extend_from_slice(&[0; 11]);
+        data.push(1);
+        assert_eq!(parse(RecordType::Aaaa, &[(28, data)]), vec!["2001:db8::1"]);
+    }
+
+    #[test]
+    fn parse_cname_and_ns() {
+        assert_eq!(
+            parse(RecordType::Cname, &[(5, www_name())]),
+            vec!["www.example.com"]
+        );
+        assert_eq!(
+            parse(RecordType::Ns, &[(2, www_name())]),
+            vec!["www.example.com"]
+        );
+    }
+
+    #[test]
+    fn parse_mx() {
+        let mut data = vec![0, 10];
+        data.extend_from_slice(&www_name());
+        assert_eq!(
+            parse(RecordType::Mx, &[(15, data)]),
+            vec!["www.example.com"]
+        );
+    }
+
+    #[test]
+    fn parse_txt() {
+        let mut data = vec![5];
+        data.extend_from_slice(b"hello");
+        data.push(5);
+        data.extend_from_slice(b"world");
+        assert_eq!(parse(RecordType::Txt, &[(16, data)]), vec!["helloworld"]);
+    }
+
+    #[test]
+    fn parse_skips_cname_chain() {
+        let records = parse(RecordType::A, &[(5, www_name()), (1, vec![192, 0, 2, 1])]);
+        assert_eq!(records, vec!["192.0.2.1"]);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_records() {
+        let query = example_query(RecordType::A);
+        let packet = response(&query, 0x8180, &[(1, vec![192, 0, 2])]);
+        assert!(parse_response(&packet, &query, RecordType::A).is_err());
+        let txt_query = example_query(RecordType::Txt);
+        let packet = response(&txt_query, 0x8180, &[(16, vec![5, b'a'])]);
+        assert!(parse_response(&packet, &txt_query, RecordType::Txt).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_truncated_packets() {
+        let query = example_query(RecordType::A);
+        let packet = response(&query, 0x8180, &[(1, vec![192, 0, 2, 1])]);
+        for size in 0..packet.len() {
+            assert!(parse_response(&packet[..size], &query, RecordType::A).is_err());
+        }
+        let packet = response(&query, 0x8380, &[]);
+        assert!(parse_response(&packet, &query, RecordType::A).is_err());
+    }
+
+    #[test]
+    fn parse_reports_errors() {
+        let query = example_query(RecordType::A);
+        for flags in [0x8182, 0x8183, 0x8185] {
+            let packet = response(&query, flags, &[]);
+            assert!(parse_response(&packet, &query, RecordType::A).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_checks_query() {
+        let query = example_query(RecordType::A);
+        let packet = response(&query, 0x8180, &[(1, vec![192, 0, 2, 1])]);
+
+        let mut other_id = packet.clone();
+        other_id[1] ^= 0xff;
+        assert!(parse_response(&other_id, &query, RecordType::A).is_err());
+
+        let other_type = build_query(QUERY_ID, "example.com", RecordType::Aaaa).unwrap();
+        assert!(parse_response(&packet, &other_type, RecordType::A).is_err());
+
+        let other_name = build_query(QUERY_ID, "example.org", RecordType::A).unwrap();
+        assert!(parse_response(&packet, &other_name, RecordType::A).is_err());
+
+        let not_a_response = response(&query, 0x0180, &[(1, vec![192, 0, 2, 1])]);
+        assert!(parse_response(&not_a_response, &query, RecordType::A).is_err());
+
+        let upper_case = build_query(QUERY_ID, "EXAMPLE.com", RecordType::A).unwrap();
+        let records = parse_response(&packet, &upper_case, RecordType::A).unwrap();
+        assert_eq!(records, vec!["192.0.2.1"]);
+    }
+
+    #[test]
+    fn read_name_follows_pointers() {
+        let query = example_query(RecordType::A);
+        let mut packet = response(&query, 0x8180, &[]);
+        let www = packet.len();
+        packet.extend_from_slice(&www_name());
+        let pointer_to_www = packet.len();
+        packet.extend_from_slice(&[0xc0, www as u8]);
+        assert_eq!(read_name(&packet, HEADER_SIZE).unwrap(), "example.com");
+        assert_eq!(read_name(&packet, www).unwrap(), "www.example.com");
+        assert_eq!(
+            read_name(&packet, pointer_to_www).unwrap(),
+            "www.example.com"
+        );
+    }
+
+    #[test]
+    fn read_name_rejects_pointer_loops() {
+        let mut packet = vec![0; HEADER_SIZE];
+        packet.extend_from_slice(&NAME_POINTER);
+        assert!(read_name(&packet, HEADER_SIZE).is_err());
+
+        let mut packet = vec![0; HEADER_SIZE];
+        packet.extend_from_slice(&[1, b'a', 0xc0, 0x0c]);
+        assert!(read_name(&packet, HEADER_SIZE).is_err());
+    }
+
+    #[test]
+    fn read_name_rejects_truncated_names() {
+        let mut packet = vec![0; HEADER_SIZE];
+        packet.extend_from_slice(&[7, b'e', b'x', b'a']);
+        assert!(read_name(&packet, HEADER_SIZE).is_err());
+        let mut packet = vec![0; HEADER_SIZE];
+        packet.extend_from_slice(&[3, b'c', b'o', b'm']);
+        assert!(read_name(&packet, HEADER_SIZE).is_err());
+        let mut packet = vec![0; HEADER_SIZE];
+        packet.push(0xc0);
+        assert!(read_name(&packet, HEADER_SIZE).is_err());
+        assert!(read_name(&packet, packet.len() + 5).is_err());
+    }
+
+    #[tokio::test]
+    async fn truncated_udp_answer_is_retried_over_tcp() {
+        let tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let resolver = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind(resolver).await.unwrap();
+        tokio::spawn(async move {
+            let mut query = vec![0; MAX_PACKET_SIZE];
+            let (size, client) = udp.recv_from(&mut query).await.unwrap();
+            let packet = response(&query[..size], 0x8380, &[]);
+            udp.send_to(&packet, client).await.unwrap();
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let length = stream.read_u16().await.unwrap();
+            let mut query = vec![0; length as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            let packet = response(&query, 0x8180, &[(1, vec![192, 0, 2, 1])]);
+            stream.write_u16(packet.len() as u16).await.unwrap();
+            stream.write_all(&packet).await.unwrap();
+        });
+        let records = resolve(
+            resolver,
+            "example.com",
+            RecordType::A,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(records, vec!["192.0.2.1"]);
+    }
+}
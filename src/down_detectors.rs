@@ -1,21 +1,146 @@
-use crate::utils::request_agent;
+use crate::config;
+use crate::dns::{self, RecordType};
+use crate::metrics;
+use crate::utils::{request_agent, MeasuredSend};
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
 use log::{error, info, trace, warn};
-use reqwest::StatusCode;
-use std::cmp::min;
-use std::env::{self, VarError};
+use regex::Regex;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, VecDeque};
 use std::error::Error;
 use std::fmt::Display;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
 use tokio::sync::RwLock;
-use tokio::time::Duration;
+use tokio::time::{timeout, Duration, Instant};
 
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
 use async_trait::async_trait;
 
 const HIGH_ERROR_RATE: f32 = 0.1;
+const DEFAULT_TIMEOUT_S: u64 = 10;
+const DEFAULT_SLO_PERCENTILE: u8 = 95;
+const DEFAULT_REPORT_HOUR: u32 = 9;
+const DAY_S: i64 = 24 * 3600;
+// Longest uptime window, older transitions are forgotten
+const AVAILABILITY_WINDOW_S: i64 = 30 * DAY_S;
 pub struct DownDetectors {
     watch_list: Vec<RwLock<DownDetector>>,
+    report_day: Option<Weekday>,
+    report_hour: u32,
+    // ISO week of the last availability report, e.g. 2024-W07
+    last_report: RwLock<Option<String>>,
+}
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_NAME",
+            "Friendly name of what is watched, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_URL",
+            "URL of what is watched: http(s)://..., tcp://host:port or dns://name, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_METHOD",
+            "HTTP method of the check (default: GET)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_BODY",
+            "Body sent with the request (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_HEADER_0_NAME",
+            "Name of an additional HTTP header, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_HEADER_0_VALUE",
+            "Value of an additional HTTP header, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_STATUS",
+            "Accepted status codes or ranges, e.g. 200,204,400-499 (default: 200)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_BODY_CONTAINS",
+            "Response body must contain this text (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_BODY_REGEX",
+            "Response body must match this regex (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_JSON_0_PATH",
+            "Path which must exist in the JSON response, e.g. $.status or $.items[0].state, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_JSON_0_VALUE",
+            "Expected value at the JSON path (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_TIMEOUT",
+            "Timeout of the check in seconds (default: 10 for tcp and dns, none for http)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_LATENCY_SLO_MS",
+            "Target is degraded when its latency percentile is above this value in ms (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_LATENCY_SLO_PERCENTILE",
+            "Latency percentile compared to the SLO: 50, 95 or 99 (default: 95)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_DNS_RESOLVER",
+            "Resolver used by dns:// checks, ip or ip:port (default: first nameserver of /etc/resolv.conf)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_DNS_TYPE",
+            "Record type queried by dns:// checks: A, AAAA, CNAME, MX, NS or TXT (default: A)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_DNS_EXPECTED",
+            "Records which must be part of the answer, comma separated (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_0_SLO",
+            "Availability objective in percent, e.g. 99.9, used to compute the error budget (optional)",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_REPORT_DAY",
+            "Post a weekly availability report on this day (mon, tue, ...), no report if not set",
+            false,
+        ),
+        ModuleParam::new(
+            "DOWN_DETECTORS_REPORT_HOUR",
+            "Hour (UTC) of the weekly availability report (default: 9)",
+            false,
+        ),
+    ]
 }
 
 #[async_trait]
@@ -24,28 +149,17 @@ impl Module for DownDetectors {
         "down_detectors"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
+    fn variation_durations(&self) -> Vec<Duration> {
         vec![
-            ModuleParam::new(
-                "DOWN_DETECTORS_0_NAME",
-                "Friendly name of what is watched, can be multiple (0..)",
-                false,
-            ),
-            ModuleParam::new(
-                "DOWN_DETECTORS_0_URL",
-                "URL of what is watched, can be multiple (0..)",
-                false,
-            ),
+            Duration::from_secs(2),
+            Duration::from_secs(2),
+            Duration::from_secs(600),
         ]
     }
 
-    fn variation_durations(&self) -> Vec<Duration> {
-        vec![Duration::from_secs(2), Duration::from_secs(2)]
-    }
-
     fn capabilities(&self) -> ModuleCapabilities {
         ModuleCapabilities {
-            triggers: Some(vec!["/status".to_string()]),
+            triggers: Some(vec!["/status".to_string(), "/uptime".to_string()]),
             ..ModuleCapabilities::default()
         }
     }
@@ -59,6 +173,7 @@ impl Module for DownDetectors {
                 None
             }
             1 => self.run_alive().await,
+            2 => self.run_report().await,
             var => {
                 error!("variation {var} is not managed");
                 None
@@ -66,14 +181,23 @@ impl Module for DownDetectors {
         }
     }
 
-    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+    async fn trigger(&self, message: &str) -> Option<Vec<MessageResponse>> {
+        if let Some(args) = message.split("/uptime").nth(1) {
+            return Some(vec![self.uptime_response(args.trim()).await]);
+        }
         trace!("responding to /status");
         let mut response = String::new();
         for e in self.watch_list.iter() {
             let lock = e.read().await;
             let s = format!(
-                "{}: alive={}, error_rate={:.2}\n",
-                lock.name, lock.alive, lock.error_rate
+                "{}: alive={}, degraded={}, error_rate={:.2}, latency p50={} p95={} p99={}\n",
+                lock.name,
+                lock.alive,
+                lock.degraded,
+                lock.error_rate,
+                format_latency(lock.percentile(50)),
+                format_latency(lock.percentile(95)),
+                format_latency(lock.percentile(99))
             );
             response.push_str(s.as_str());
         }
@@ -87,18 +211,71 @@ impl Module for DownDetectors {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let mut state = DownDetectorsState {
+            last_report: self.last_report.read().await.clone(),
+            saved_at: Utc::now().timestamp(),
+            ..DownDetectorsState::default()
+        };
+        for down_detector in self.watch_list.iter() {
+            let lock = down_detector.read().await;
+            state
+                .availabilities
+                .insert(lock.name.clone(), lock.availability.clone());
+        }
+        serde_json::to_value(state).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let mut state: DownDetectorsState = match serde_json::from_value(state) {
+            Ok(state) => state,
+            Err(err) => {
+                error!("cannot load down_detectors state: {}", err);
+                return;
+            }
+        };
+        *self.last_report.write().await = state.last_report;
+        let now = Utc::now().timestamp();
+        for down_detector in self.watch_list.iter() {
+            let mut lock = down_detector.write().await;
+            if let Some(mut availability) = state.availabilities.remove(&lock.name) {
+                // Nothing was probed while the bot was stopped
+                availability.add_gap(state.saved_at, now);
+                // Targets are considered alive at startup until probes tell otherwise
+                if availability
+                    .transitions
+                    .back()
+                    .is_some_and(|last| !last.alive)
+                {
+                    availability.record(true, now);
+                }
+                lock.availability = availability;
+            }
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct DownDetectorsState {
+    availabilities: BTreeMap<String, Availability>,
+    last_report: Option<String>,
+    saved_at: i64,
 }
 
 impl DownDetectors {
-    pub fn new() -> Result<DownDetectors, VarError> {
+    pub fn new() -> Result<DownDetectors, Box<dyn Error + Send + Sync>> {
         let mut watch_list = Vec::new();
         for i in 0..100 {
-            let name = env::var(format!("DOWN_DETECTORS_{}_NAME", i));
-            let url = env::var(format!("DOWN_DETECTORS_{}_URL", i));
+            let name = config::var(format!("DOWN_DETECTORS_{}_NAME", i));
+            let url = config::var(format!("DOWN_DETECTORS_{}_URL", i));
             match (name, url) {
                 (Ok(name), Ok(url)) => {
                     info!("down detector on {} configured", name);
-                    let new = DownDetector::new(name, url);
+                    let probe = Probe::from_config(i, &name, &url)?;
+                    let latency_slo = LatencySlo::from_config(i, &name)?;
+                    let slo = slo_var(i, &name)?;
+                    let new = DownDetector::new(name, url, probe, latency_slo, slo);
                     watch_list.push(RwLock::new(new));
                 }
                 _ => break,
@@ -107,18 +284,93 @@ impl DownDetectors {
         if watch_list.is_empty() {
             warn!("down detectors module enabled bot not configuration provided");
         }
-        Ok(DownDetectors { watch_list })
+        let report_day = match config::var("DOWN_DETECTORS_REPORT_DAY") {
+            Ok(day) => Some(
+                day.parse::<Weekday>()
+                    .map_err(|_| format!("DOWN_DETECTORS_REPORT_DAY: unknown day '{}'", day))?,
+            ),
+            Err(_) => None,
+        };
+        let report_hour = match config::var("DOWN_DETECTORS_REPORT_HOUR") {
+            Ok(hour) => match hour.parse() {
+                Ok(hour @ 0..=23) => hour,
+                _ => {
+                    return Err(
+                        format!("DOWN_DETECTORS_REPORT_HOUR: invalid hour '{}'", hour).into(),
+                    )
+                }
+            },
+            Err(_) => DEFAULT_REPORT_HOUR,
+        };
+        Ok(DownDetectors {
+            watch_list,
+            report_day,
+            report_hour,
+            last_report: RwLock::new(None),
+        })
+    }
+
+    async fn uptime_response(&self, target: &str) -> String {
+        trace!("responding to /uptime");
+        let now = Utc::now().timestamp();
+        let mut response = String::new();
+        for down_detector in self.watch_list.iter() {
+            let lock = down_detector.read().await;
+            if target.is_empty() {
+                response.push_str(&format!("{}\n", lock.uptime_summary(now)));
+            } else if lock.name.eq_ignore_ascii_case(target) {
+                return lock.uptime_details(now);
+            }
+        }
+        if !target.is_empty() {
+            let mut names = Vec::new();
+            for down_detector in self.watch_list.iter() {
+                names.push(down_detector.read().await.name.clone());
+            }
+            return format!(
+                "{} is not watched, watched targets: {}",
+                target,
+                names.join(", ")
+            );
+        }
+        if response.is_empty() {
+            return "no target watched".to_string();
+        }
+        response
+    }
+
+    async fn run_report(&self) -> Option<Vec<Message>> {
+        let report_day = self.report_day?;
+        let now = Utc::now();
+        if now.weekday() != report_day || now.hour() < self.report_hour {
+            return None;
+        }
+        let week = now.format("%G-W%V").to_string();
+        let mut last_report = self.last_report.write().await;
+        if last_report.as_ref() == Some(&week) {
+            return None;
+        }
+        trace!("posting weekly availability report of {}", week);
+        *last_report = Some(week);
+        let mut report = String::from("📈 Weekly availability report:\n");
+        for down_detector in self.watch_list.iter() {
+            let lock = down_detector.read().await;
+            report.push_str(&format!("- {}\n", lock.uptime_summary(now.timestamp())));
+        }
+        Some(vec![report.trim_end().to_string()])
     }
 
     async fn run_error_rate(&self) {
         for down_detector in self.watch_list.iter() {
-            let (name, url) = {
+            let (name, url, probe) = {
                 let lock = down_detector.read().await;
-                (lock.name.clone(), lock.url.clone())
+                (lock.name.clone(), lock.url.clone(), lock.probe.clone())
             };
-            let probe = DownDetector::test_url(&name, &url).await;
+            let probe = probe.run(&name, &url).await;
             let mut lock = down_detector.write().await;
-            if let Some(error_rate) = lock.update_error_rate(probe) {
+            // Latencies and probe metrics are only recorded by run_alive to count each target once
+            let error_rate = lock.update_error_rate(probe);
+            if let Some(error_rate) = error_rate {
                 if error_rate > HIGH_ERROR_RATE {
                     warn!(
                         "high error rate on {}: {:?}%",
@@ -133,16 +385,30 @@ impl DownDetectors {
     async fn run_alive(&self) -> Option<Vec<Message>> {
         let mut messages = Vec::<Message>::new();
         for down_detector in self.watch_list.iter() {
-            let (name, url) = {
+            let (name, url, probe) = {
                 let lock = down_detector.read().await;
-                (lock.name.clone(), lock.url.clone())
+                (lock.name.clone(), lock.url.clone(), lock.probe.clone())
             };
-            let probe = DownDetector::test_url(&name, &url).await;
+            let probe = probe.run(&name, &url).await;
+            let success = probe.is_ok();
             let mut lock = down_detector.write().await;
+            if let Ok(latency) = probe.as_ref() {
+                lock.record_latency(*latency);
+            }
             let alive_change = lock.update_alive(probe);
+            if alive_change.0 != alive_change.1 {
+                let alive = lock.alive;
+                lock.availability.record(alive, Utc::now().timestamp());
+            }
+            metrics::probe(&lock.name, success, lock.alive, lock.error_rate);
             if let Some(response) = lock.build_alive_message(alive_change) {
                 messages.push(response);
             }
+            let degraded_change = lock.update_degraded();
+            metrics::probe_degraded(&lock.name, lock.degraded);
+            if let Some(response) = lock.build_degraded_message(degraded_change) {
+                messages.push(response);
+            }
         }
         if messages.is_empty() {
             return None;
@@ -155,55 +421,118 @@ impl DownDetectors {
 struct DownDetector {
     name: String,
     url: String,
+    probe: Probe,
     alive: bool,
     access_failure_cnt: u8,
     last_error: Option<DownDetectorError>,
     error_rate_acc: f32,
     error_rate_cnt: u32,
     error_rate: f32,
+    // Latencies of the last successful probes, oldest first
+    latencies: VecDeque<Duration>,
+    latency_slo: Option<LatencySlo>,
+    degraded: bool,
+    availability: Availability,
+    // Availability objective in percent
+    slo: Option<f64>,
 }
 
 impl DownDetector {
-    fn new(name: String, url: String) -> Self {
+    fn new(
+        name: String,
+        url: String,
+        probe: Probe,
+        latency_slo: Option<LatencySlo>,
+        slo: Option<f64>,
+    ) -> Self {
         DownDetector {
             name,
             url,
+            probe,
             alive: true,
             access_failure_cnt: 0,
             last_error: None,
             error_rate_acc: 0.0,
             error_rate_cnt: 0,
             error_rate: 0.0,
+            latencies: VecDeque::new(),
+            latency_slo,
+            degraded: false,
+            availability: Availability::new(Utc::now().timestamp()),
+            slo,
         }
     }
 
-    async fn test_url(name: &str, url: &str) -> Result<(), DownDetectorError> {
-        let agent = match request_agent() {
-            Ok(agent) => agent,
-            Err(err) => {
-                trace!("{}: agent init: {}", name, err);
-                return Err(DownDetectorError::AgentInit(err.to_string()));
-            }
-        };
-
-        let response = match agent.get(url).send().await {
-            Ok(response) => response,
-            Err(err) => {
-                trace!("{}: post: {}", name, err);
-                return Err(DownDetectorError::from_reqwest(err));
-            }
-        };
+    fn uptime_summary(&self, now: i64) -> String {
+        let mut summary = format!("{}:", self.name);
+        for (label, window) in [("24h", DAY_S), ("7d", 7 * DAY_S), ("30d", 30 * DAY_S)] {
+            let uptime = match self.availability.uptime(window, now) {
+                Some(uptime) => format!("{:.2}%", uptime.percent()),
+                None => "n/a".to_string(),
+            };
+            summary.push_str(&format!(" {} {},", label, uptime));
+        }
+        summary.pop();
+        if let Some(slo) = self.slo {
+            let budget = self
+                .availability
+                .uptime(AVAILABILITY_WINDOW_S, now)
+                .map(|uptime| format!("{:.0}% of error budget used", uptime.budget_used(slo)))
+                .unwrap_or("error budget n/a".to_string());
+            summary.push_str(&format!(" (SLO {}%, {})", slo, budget));
+        }
+        summary
+    }
 
-        match response.status() {
-            StatusCode::OK => Ok(()),
-            bad_code => {
-                trace!("{}: {}", name, bad_code);
-                Err(DownDetectorError::Code(bad_code.as_u16()))
+    fn uptime_details(&self, now: i64) -> String {
+        let mut details = format!(
+            "{} availability since {}:\n",
+            self.name,
+            format_date(max(self.availability.since, now - AVAILABILITY_WINDOW_S))
+        );
+        for (label, window) in [("24h", DAY_S), ("7d", 7 * DAY_S), ("30d", 30 * DAY_S)] {
+            if let Some(uptime) = self.availability.uptime(window, now) {
+                details.push_str(&format!(
+                    "- {}: {:.3}% ({} down)\n",
+                    label,
+                    uptime.percent(),
+                    format_duration(uptime.down)
+                ));
             }
         }
+        if let (Some(slo), Some(uptime)) = (
+            self.slo,
+            self.availability.uptime(AVAILABILITY_WINDOW_S, now),
+        ) {
+            details.push_str(&format!(
+                "SLO {}%: {:.0}% of the error budget used ({} down of {} allowed)\n",
+                slo,
+                uptime.budget_used(slo),
+                format_duration(uptime.down),
+                format_duration(uptime.budget(slo))
+            ));
+        }
+        let transitions = self
+            .availability
+            .transitions
+            .iter()
+            .rev()
+            .take(5)
+            .map(|transition| {
+                let state = match transition.alive {
+                    true => "up",
+                    false => "down",
+                };
+                format!("{} on {}", state, format_date(transition.at))
+            })
+            .collect::<Vec<String>>();
+        if !transitions.is_empty() {
+            details.push_str(&format!("last changes: {}\n", transitions.join(", ")));
+        }
+        details
     }
 
-    fn update_alive(&mut self, probe: Result<(), DownDetectorError>) -> (bool, bool) {
+    fn update_alive(&mut self, probe: Result<Duration, DownDetectorError>) -> (bool, bool) {
         // Schmitt Trigger based on the number of errors
         // https://en.wikipedia.org/wiki/Schmitt_trigger
         const LOW: u8 = 3;
@@ -232,7 +561,7 @@ impl DownDetector {
         (alive_old, self.alive)
     }
 
-    fn update_error_rate(&mut self, probe: Result<(), DownDetectorError>) -> Option<f32> {
+    fn update_error_rate(&mut self, probe: Result<Duration, DownDetectorError>) -> Option<f32> {
         // A simple sliding mean, only providing value once sliding window is full.
         const SIZE: f32 = 100.0;
         self.error_rate_acc = match probe {
@@ -248,6 +577,77 @@ impl DownDetector {
         }
     }
 
+    fn record_latency(&mut self, latency: Duration) {
+        // Same window size as the error rate
+        const SIZE: usize = 100;
+        if self.latencies.len() >= SIZE {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+        metrics::probe_latency(&self.name, latency);
+    }
+
+    // Nearest-rank percentile of the recorded latencies
+    fn percentile(&self, percentile: u8) -> Option<Duration> {
+        let mut sorted = self.latencies.iter().copied().collect::<Vec<Duration>>();
+        sorted.sort();
+        let rank = (percentile as usize * sorted.len()).div_ceil(100).max(1);
+        sorted.get(rank - 1).copied()
+    }
+
+    fn update_degraded(&mut self) -> (bool, bool) {
+        // Latency is only judged on enough samples and needs to go 10% below the SLO to
+        // recover, avoiding flapping around the threshold
+        const MIN_SAMPLES: usize = 10;
+        const RECOVERY_RATIO: f64 = 0.9;
+        let degraded_old = self.degraded;
+        let Some(slo) = self.latency_slo.as_ref() else {
+            return (degraded_old, degraded_old);
+        };
+        // Down targets are not degraded, their latency is judged again once they are up
+        if !self.alive {
+            self.degraded = false;
+            return (degraded_old, degraded_old);
+        }
+        if self.latencies.len() < MIN_SAMPLES {
+            return (degraded_old, degraded_old);
+        }
+        let Some(latency) = self.percentile(slo.percentile) else {
+            return (degraded_old, degraded_old);
+        };
+        self.degraded = match self.degraded {
+            false => latency > slo.threshold,
+            true => latency > slo.threshold.mul_f64(RECOVERY_RATIO),
+        };
+        if degraded_old != self.degraded {
+            warn!(
+                "{}: degraded went from {} to {}",
+                self.name, degraded_old, self.degraded
+            );
+        }
+        (degraded_old, self.degraded)
+    }
+
+    fn build_degraded_message(&self, degraded_change: (bool, bool)) -> Option<String> {
+        let slo = self.latency_slo.as_ref()?;
+        let latency = format_latency(self.percentile(slo.percentile));
+        match degraded_change {
+            (false, true) => Some(format!(
+                "[{}]({}) is degraded: p{} latency is {}, above {}",
+                self.name,
+                self.url,
+                slo.percentile,
+                latency,
+                format_latency(Some(slo.threshold))
+            )),
+            (true, false) => Some(format!(
+                "[{}]({}) latency is back to normal: p{} latency is {}",
+                self.name, self.url, slo.percentile, latency
+            )),
+            _ => None,
+        }
+    }
+
     fn build_alive_message(&self, alive_change: (bool, bool)) -> Option<String> {
         let response = match alive_change {
             (true, false) => match &self.last_error {
@@ -275,11 +675,561 @@ impl DownDetector {
     }
 }
 
+#[derive(Clone)]
+struct LatencySlo {
+    threshold: Duration,
+    percentile: u8,
+}
+
+impl LatencySlo {
+    fn from_config(index: usize, name: &str) -> Result<Option<Self>, String> {
+        let var = |suffix: &str| config::var(format!("DOWN_DETECTORS_{}_{}", index, suffix)).ok();
+        let Some(threshold) = var("LATENCY_SLO_MS") else {
+            return Ok(None);
+        };
+        let threshold = threshold
+            .parse()
+            .map_err(|err| format!("{}: invalid latency SLO {}: {}", name, threshold, err))?;
+        let percentile = match var("LATENCY_SLO_PERCENTILE") {
+            Some(percentile) => match percentile.trim_start_matches(['p', 'P']).parse() {
+                Ok(percentile @ (50 | 95 | 99)) => percentile,
+                _ => {
+                    return Err(format!(
+                        "{}: latency percentile must be 50, 95 or 99, got {}",
+                        name, percentile
+                    ))
+                }
+            },
+            None => DEFAULT_SLO_PERCENTILE,
+        };
+        Ok(Some(LatencySlo {
+            threshold: Duration::from_millis(threshold),
+            percentile,
+        }))
+    }
+}
+
+fn slo_var(index: usize, name: &str) -> Result<Option<f64>, String> {
+    let Ok(slo) = config::var(format!("DOWN_DETECTORS_{}_SLO", index)) else {
+        return Ok(None);
+    };
+    match slo.trim_end_matches('%').parse::<f64>() {
+        Ok(slo) if slo > 0.0 && slo < 100.0 => Ok(Some(slo)),
+        _ => Err(format!(
+            "{}: SLO must be a percentage between 0 and 100, got {}",
+            name, slo
+        )),
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct Availability {
+    // Start of the observation, nothing is known before
+    since: i64,
+    // Alive state changes, oldest first
+    transitions: VecDeque<Transition>,
+    // Periods without probes, e.g. while the bot was stopped, oldest first
+    gaps: VecDeque<Gap>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Gap {
+    from: i64,
+    to: i64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Transition {
+    at: i64,
+    alive: bool,
+}
+
+// Observed and down durations in seconds
+struct Uptime {
+    observed: i64,
+    down: i64,
+}
+
+impl Availability {
+    fn new(now: i64) -> Self {
+        Availability {
+            since: now,
+            transitions: VecDeque::new(),
+            gaps: VecDeque::new(),
+        }
+    }
+
+    fn add_gap(&mut self, from: i64, to: i64) {
+        if from < to {
+            self.gaps.push_back(Gap { from, to });
+        }
+        while self
+            .gaps
+            .front()
+            .is_some_and(|gap| gap.to < to - AVAILABILITY_WINDOW_S)
+        {
+            self.gaps.pop_front();
+        }
+    }
+
+    // Unobserved seconds between from and to
+    fn unobserved(&self, from: i64, to: i64) -> i64 {
+        self.gaps
+            .iter()
+            .map(|gap| max(0, min(gap.to, to) - max(gap.from, from)))
+            .sum()
+    }
+
+    fn record(&mut self, alive: bool, now: i64) {
+        self.transitions.push_back(Transition { at: now, alive });
+        // Keep the last transition before the window, it gives the state at its start
+        while self
+            .transitions
+            .get(1)
+            .is_some_and(|transition| transition.at < now - AVAILABILITY_WINDOW_S)
+        {
+            self.transitions.pop_front();
+        }
+    }
+
+    fn uptime(&self, window: i64, now: i64) -> Option<Uptime> {
+        let start = max(now - window, self.since);
+        if start >= now {
+            return None;
+        }
+        let mut alive = self
+            .transitions
+            .iter()
+            .rev()
+            .find(|transition| transition.at <= start)
+            .is_none_or(|transition| transition.alive);
+        let mut cursor = start;
+        let mut down = 0;
+        for transition in self.transitions.iter().filter(|t| t.at > start) {
+            if !alive {
+                down += transition.at - cursor - self.unobserved(cursor, transition.at);
+            }
+            cursor = transition.at;
+            alive = transition.alive;
+        }
+        if !alive {
+            down += now - cursor - self.unobserved(cursor, now);
+        }
+        let observed = now - start - self.unobserved(start, now);
+        if observed <= 0 {
+            return None;
+        }
+        Some(Uptime { observed, down })
+    }
+}
+
+impl Uptime {
+    fn percent(&self) -> f64 {
+        100.0 * (self.observed - self.down) as f64 / self.observed as f64
+    }
+
+    // Allowed downtime in seconds
+    fn budget(&self, slo: f64) -> i64 {
+        (self.observed as f64 * (100.0 - slo) / 100.0) as i64
+    }
+
+    fn budget_used(&self, slo: f64) -> f64 {
+        match self.budget(slo) {
+            0 if self.down == 0 => 0.0,
+            0 => 100.0,
+            budget => 100.0 * self.down as f64 / budget as f64,
+        }
+    }
+}
+
+fn format_date(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or(timestamp.to_string())
+}
+
+// e.g. 2d 3h, 3h 12m, 15m or 42s
+fn format_duration(seconds: i64) -> String {
+    let (days, hours, minutes) = (seconds / DAY_S, seconds % DAY_S / 3600, seconds % 3600 / 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", seconds),
+        (0, 0, minutes) => format!("{}m", minutes),
+        (0, hours, minutes) => format!("{}h {}m", hours, minutes),
+        (days, hours, _) => format!("{}d {}h", days, hours),
+    }
+}
+
+fn format_latency(latency: Option<Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{}ms", latency.as_millis()),
+        None => "n/a".to_string(),
+    }
+}
+
+#[derive(Clone)]
+enum Probe {
+    Http(HttpCheck),
+    Tcp(TcpCheck),
+    Dns(DnsCheck),
+}
+
+impl Probe {
+    fn from_config(
+        index: usize,
+        name: &str,
+        url: &str,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        match url.split_once("://") {
+            Some(("tcp", address)) => Ok(Probe::Tcp(TcpCheck {
+                address: address.trim_end_matches('/').to_string(),
+                timeout: timeout_var(index, name)?
+                    .unwrap_or(Duration::from_secs(DEFAULT_TIMEOUT_S)),
+            })),
+            Some(("dns", dns_name)) => Ok(Probe::Dns(DnsCheck::from_config(
+                index,
+                name,
+                dns_name.trim_end_matches('/'),
+            )?)),
+            _ => Ok(Probe::Http(HttpCheck::from_config(index, name)?)),
+        }
+    }
+
+    // Returns the latency of successful checks
+    async fn run(&self, name: &str, url: &str) -> Result<Duration, DownDetectorError> {
+        let start = Instant::now();
+        match self {
+            Probe::Http(check) => check.run(name, url).await,
+            Probe::Tcp(check) => check.run(name).await,
+            Probe::Dns(check) => check.run(name).await,
+        }?;
+        let latency = start.elapsed();
+        trace!("{}: check succeeded in {:?}", name, latency);
+        Ok(latency)
+    }
+}
+
+fn timeout_var(index: usize, name: &str) -> Result<Option<Duration>, String> {
+    let Ok(timeout) = config::var(format!("DOWN_DETECTORS_{}_TIMEOUT", index)) else {
+        return Ok(None);
+    };
+    match timeout.parse() {
+        Ok(timeout) => Ok(Some(Duration::from_secs(timeout))),
+        Err(err) => Err(format!("{}: invalid timeout {}: {}", name, timeout, err)),
+    }
+}
+
+#[derive(Clone)]
+struct TcpCheck {
+    // host:port
+    address: String,
+    timeout: Duration,
+}
+
+impl TcpCheck {
+    async fn run(&self, name: &str) -> Result<(), DownDetectorError> {
+        match timeout(self.timeout, TcpStream::connect(&self.address)).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(err)) => {
+                trace!("{}: connect: {}", name, err);
+                Err(DownDetectorError::Transport(err.to_string()))
+            }
+            Err(_) => Err(DownDetectorError::Transport(format!(
+                "no connection after {}s",
+                self.timeout.as_secs()
+            ))),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct DnsCheck {
+    dns_name: String,
+    resolver: SocketAddr,
+    record_type: RecordType,
+    expected: Vec<String>,
+    timeout: Duration,
+}
+
+impl DnsCheck {
+    fn from_config(index: usize, name: &str, dns_name: &str) -> Result<Self, String> {
+        let var = |suffix: &str| config::var(format!("DOWN_DETECTORS_{}_{}", index, suffix)).ok();
+        let resolver = match var("DNS_RESOLVER") {
+            Some(resolver) => dns::parse_resolver(&resolver)
+                .ok_or_else(|| format!("{}: invalid DNS resolver {}", name, resolver))?,
+            None => dns::system_resolver()
+                .ok_or_else(|| format!("{}: no nameserver found in /etc/resolv.conf", name))?,
+        };
+        let record_type = match var("DNS_TYPE") {
+            Some(record_type) => record_type
+                .parse()
+                .map_err(|err| format!("{}: {}", name, err))?,
+            None => RecordType::A,
+        };
+        let expected = var("DNS_EXPECTED")
+            .map(|expected| {
+                expected
+                    .split(',')
+                    .map(|record| normalize_record(record.trim()))
+                    .filter(|record| !record.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(DnsCheck {
+            dns_name: dns_name.to_string(),
+            resolver,
+            record_type,
+            expected,
+            timeout: timeout_var(index, name)?.unwrap_or(Duration::from_secs(DEFAULT_TIMEOUT_S)),
+        })
+    }
+
+    async fn run(&self, name: &str) -> Result<(), DownDetectorError> {
+        let records = dns::resolve(
+            self.resolver,
+            &self.dns_name,
+            self.record_type,
+            self.timeout,
+        )
+        .await
+        .map_err(|err| {
+            trace!("{}: resolve: {}", name, err);
+            DownDetectorError::Dns(err.to_string())
+        })?;
+        if records.is_empty() {
+            return Err(DownDetectorError::Dns(format!(
+                "no {} record found",
+                self.record_type
+            )));
+        }
+        let records = records
+            .iter()
+            .map(|record| normalize_record(record))
+            .collect::<Vec<String>>();
+        if let Some(missing) = self
+            .expected
+            .iter()
+            .find(|expected| !records.contains(expected))
+        {
+            return Err(DownDetectorError::Assertion(format!(
+                "{} record {} not found, got {}",
+                self.record_type,
+                missing,
+                records.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn normalize_record(record: &str) -> String {
+    record.trim_end_matches('.').to_lowercase()
+}
+
+#[derive(Clone)]
+struct HttpCheck {
+    method: Method,
+    body: Option<String>,
+    headers: Vec<(String, String)>,
+    // Inclusive ranges of accepted status codes
+    status: Vec<(u16, u16)>,
+    body_contains: Option<String>,
+    body_regex: Option<Regex>,
+    json: Vec<JsonAssertion>,
+    // Only the connect timeout of the HTTP client applies if not set
+    timeout: Option<Duration>,
+}
+
+#[derive(Clone)]
+struct JsonAssertion {
+    path: String,
+    pointer: String,
+    value: Option<String>,
+}
+
+impl HttpCheck {
+    fn from_config(index: usize, name: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let var = |suffix: &str| config::var(format!("DOWN_DETECTORS_{}_{}", index, suffix)).ok();
+        let method = var("METHOD").unwrap_or("GET".to_string());
+        let method = Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|_| format!("{}: invalid HTTP method {}", name, method))?;
+        let status = match var("STATUS") {
+            Some(status) => parse_status(&status)
+                .ok_or_else(|| format!("{}: invalid status codes {}", name, status))?,
+            None => vec![(200, 200)],
+        };
+        let body_regex = match var("BODY_REGEX") {
+            Some(pattern) => Some(
+                Regex::new(&pattern)
+                    .map_err(|err| format!("{}: invalid body regex: {}", name, err))?,
+            ),
+            None => None,
+        };
+        let mut headers = Vec::new();
+        for i in 0..100 {
+            match (
+                var(&format!("HEADER_{}_NAME", i)),
+                var(&format!("HEADER_{}_VALUE", i)),
+            ) {
+                (Some(name), Some(value)) => headers.push((name, value)),
+                _ => break,
+            }
+        }
+        let mut json = Vec::new();
+        for i in 0..100 {
+            let Some(path) = var(&format!("JSON_{}_PATH", i)) else {
+                break;
+            };
+            json.push(JsonAssertion {
+                pointer: json_pointer(&path),
+                path,
+                value: var(&format!("JSON_{}_VALUE", i)),
+            });
+        }
+        Ok(HttpCheck {
+            method,
+            body: var("BODY"),
+            headers,
+            status,
+            body_contains: var("BODY_CONTAINS"),
+            body_regex,
+            json,
+            timeout: timeout_var(index, name)?,
+        })
+    }
+
+    async fn run(&self, name: &str, url: &str) -> Result<(), DownDetectorError> {
+        let agent = match request_agent() {
+            Ok(agent) => agent,
+            Err(err) => {
+                trace!("{}: agent init: {}", name, err);
+                return Err(DownDetectorError::AgentInit(err.to_string()));
+            }
+        };
+
+        let mut request = agent.request(self.method.clone(), url);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        for (header_name, value) in self.headers.iter() {
+            request = request.header(header_name, value);
+        }
+        if let Some(body) = self.body.as_ref() {
+            request = request.body(body.clone());
+        }
+        let response = match request.send_measured().await {
+            Ok(response) => response,
+            Err(err) => {
+                trace!("{}: {}: {}", name, self.method, err);
+                return Err(match self.timeout {
+                    Some(timeout) if err.is_timeout() => DownDetectorError::Transport(format!(
+                        "no response after {}s",
+                        timeout.as_secs()
+                    )),
+                    _ => DownDetectorError::from_reqwest(err),
+                });
+            }
+        };
+
+        let code = response.status().as_u16();
+        if !self
+            .status
+            .iter()
+            .any(|(first, last)| (*first..=*last).contains(&code))
+        {
+            trace!("{}: {}", name, code);
+            return Err(DownDetectorError::Code(code));
+        }
+        if self.body_contains.is_none() && self.body_regex.is_none() && self.json.is_empty() {
+            return Ok(());
+        }
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(err) => {
+                trace!("{}: body: {}", name, err);
+                return Err(DownDetectorError::from_reqwest(err));
+            }
+        };
+        self.check_body(&body).map_err(|err| {
+            trace!("{}: {}", name, err);
+            DownDetectorError::Assertion(err)
+        })
+    }
+
+    fn check_body(&self, body: &str) -> Result<(), String> {
+        if let Some(text) = self.body_contains.as_ref() {
+            if !body.contains(text.as_str()) {
+                return Err(format!("body does not contain \"{}\"", text));
+            }
+        }
+        if let Some(regex) = self.body_regex.as_ref() {
+            if !regex.is_match(body) {
+                return Err(format!("body does not match {}", regex));
+            }
+        }
+        if self.json.is_empty() {
+            return Ok(());
+        }
+        let json: Value =
+            serde_json::from_str(body).map_err(|err| format!("invalid JSON body: {}", err))?;
+        for assertion in self.json.iter() {
+            let Some(found) = json.pointer(&assertion.pointer) else {
+                return Err(format!("{} not found", assertion.path));
+            };
+            let Some(expected) = assertion.value.as_ref() else {
+                continue;
+            };
+            let found = match found {
+                Value::String(found) => found.clone(),
+                other => other.to_string(),
+            };
+            if found != *expected {
+                return Err(format!(
+                    "{} is \"{}\", expected \"{}\"",
+                    assertion.path, found, expected
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// e.g. "200,204,400-499"
+fn parse_status(status: &str) -> Option<Vec<(u16, u16)>> {
+    status
+        .split(',')
+        .map(|range| {
+            let range = range.trim();
+            match range.split_once('-') {
+                Some((first, last)) => {
+                    Some((first.trim().parse().ok()?, last.trim().parse().ok()?))
+                }
+                None => {
+                    let code = range.parse().ok()?;
+                    Some((code, code))
+                }
+            }
+        })
+        .collect()
+}
+
+// Converts a path like $.items[0].state to a JSON pointer like /items/0/state
+fn json_pointer(path: &str) -> String {
+    path.trim_start_matches('$')
+        .replace('[', ".")
+        .replace(']', "")
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .map(|part| format!("/{}", part.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
 #[derive(Clone, Debug)]
 enum DownDetectorError {
     AgentInit(String),
     Code(u16),
     Transport(String),
+    Assertion(String),
+    Dns(String),
 }
 
 impl DownDetectorError {
@@ -298,6 +1248,34 @@ impl Display for DownDetectorError {
             DownDetectorError::Code(503) => write!(f, "target has been very properly put in maintenance mode by the wonderful ops team, thanks for your understanding"),
             DownDetectorError::Code(other) => write!(f, "target is down (error code: {})", other),
             DownDetectorError::Transport(transport) => write!(f, "target seems down (transport error: {})", transport),
+            DownDetectorError::Assertion(assertion) => write!(f, "target is unhealthy ({})", assertion),
+            DownDetectorError::Dns(err) => write!(f, "target seems down (DNS error: {})", err),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime_excludes_gaps() {
+        let mut availability = Availability::new(0);
+        availability.record(false, 1000);
+        // Bot stopped from 1500 to 3500 while the target was down
+        availability.add_gap(1500, 3500);
+        availability.record(true, 3500);
+        let uptime = availability.uptime(DAY_S, 4000).unwrap();
+        assert_eq!(uptime.observed, 2000);
+        assert_eq!(uptime.down, 500);
+
+        let mut availability = Availability::new(0);
+        // Bot stopped from 1000 to 3000 while the target was alive
+        availability.add_gap(1000, 3000);
+        let uptime = availability.uptime(DAY_S, 4000).unwrap();
+        assert_eq!(uptime.observed, 2000);
+        assert_eq!(uptime.down, 0);
+        assert!(availability.uptime(DAY_S, 1000).is_some());
+        assert!(availability.uptime(1000, 3000).is_none());
+    }
+}
@@ -1,13 +1,16 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
-use crate::utils::request_agent;
+use crate::config;
+use crate::utils::{request_agent, MeasuredSend};
 use async_trait::async_trait;
 use feed_rs::model;
 use feed_rs::parser::parse;
 use log::{error, info, warn};
 use std::cmp::Ordering;
-use std::env::{self, VarError};
+use std::collections::HashMap;
+use std::env::VarError;
 use std::error::Error;
 use tokio::sync::RwLock;
 use tokio::time::Duration;
@@ -16,19 +19,19 @@ pub struct Feeds {
     feeds: RwLock<Vec<Feed>>,
 }
 
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new("FEED_0_NAME", "Feed name, can be multiple (0..)", false),
+        ModuleParam::new("FEED_0_URL", "Feed URL, can be multiple (0..)", false),
+    ]
+}
+
 #[async_trait]
 impl Module for Feeds {
     fn name(&self) -> &'static str {
         "feeds"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        vec![
-            ModuleParam::new("FEED_0_NAME", "Feed name, can be multiple (0..)", false),
-            ModuleParam::new("FEED_0_URL", "Feed URL, can be multiple (0..)", false),
-        ]
-    }
-
     async fn module_offering(&self, _modules: &[ModuleData]) {}
 
     async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
@@ -69,14 +72,37 @@ impl Module for Feeds {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let feeds = self.feeds.read().await;
+        let latest_ids = feeds
+            .iter()
+            .filter_map(|feed| Some((feed.name.clone(), feed.latest_id.clone()?)))
+            .collect::<HashMap<String, String>>();
+        serde_json::to_value(latest_ids).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let mut latest_ids: HashMap<String, String> = match serde_json::from_value(state) {
+            Ok(latest_ids) => latest_ids,
+            Err(err) => {
+                error!("cannot load feeds state: {}", err);
+                return;
+            }
+        };
+        let mut feeds = self.feeds.write().await;
+        for feed in feeds.iter_mut() {
+            feed.latest_id = latest_ids.remove(&feed.name);
+        }
+    }
 }
 
 impl Feeds {
     pub fn new() -> Result<Feeds, VarError> {
         let mut feeds = Vec::new();
         for i in 0..100 {
-            let name = env::var(format!("FEED_{}_NAME", i));
-            let url = env::var(format!("FEED_{}_URL", i));
+            let name = config::var(format!("FEED_{}_NAME", i));
+            let url = config::var(format!("FEED_{}_URL", i));
             match (name, url) {
                 (Ok(name), Ok(url)) => {
                     info!("feed configured: {} ({}), ", name, url);
@@ -99,6 +125,7 @@ struct Feed {
     pub name: String,
     pub url: String,
     pub latest: Option<model::Entry>,
+    pub latest_id: Option<String>,
 }
 
 impl Feed {
@@ -107,26 +134,28 @@ impl Feed {
             name,
             url,
             latest: None,
+            latest_id: None,
         }
     }
 
     pub async fn update(&mut self) -> bool {
         let new_entry = self.last_entry().await;
-        let changed = match (&self.latest, &new_entry) {
+        let changed = match (&self.latest_id, &new_entry) {
             (None, None) => false,
-            (Some(old), Some(new)) => old.id != new.id,
+            (Some(old_id), Some(new)) => *old_id != new.id,
             (None, Some(_)) => false,
             (Some(_), None) => false,
         };
-        if new_entry.is_some() {
-            self.latest = new_entry;
+        if let Some(entry) = new_entry {
+            self.latest_id = Some(entry.id.clone());
+            self.latest = Some(entry);
         }
         changed
     }
 
     async fn download(&self) -> Result<model::Feed, Box<dyn Error + Send + Sync>> {
         info!("downloading feeds for {}", self.name);
-        let body = match request_agent()?.get(&self.url).send().await {
+        let body = match request_agent()?.get(&self.url).send_measured().await {
             Ok(body) => body.text().await?,
             Err(err) => {
                 error!("cannot read feed located on {}: {}", self.url, err);
@@ -0,0 +1,524 @@
+use crate::bot::ModuleParam;
+use crate::config;
+use crate::utils::{request_agent, MeasuredSend};
+use chrono::{DateTime, TimeDelta, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use lazy_static::lazy_static;
+use log::{debug, info, trace, warn};
+use regex::Regex;
+use reqwest::header::{HeaderMap, ETAG, IF_NONE_MATCH, LINK, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex as AsyncMutex;
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+// GitHub asks to wait at least one minute when hitting secondary rate limits without Retry-After
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(60);
+// Warn when less than this ratio of the quota remains
+const LOW_QUOTA_RATIO: f64 = 0.1;
+const TOKEN_RENEWAL_MARGIN: TimeDelta = TimeDelta::minutes(5);
+// Bounds of the response cache of each client, least recently used pages are evicted first
+const CACHE_MAX_PAGES: usize = 256;
+const CACHE_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+// No request is made until this instant after a rate limit response
+static BLOCKED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "GITHUB_TOKEN",
+            "Github token to make api calls, not needed when authenticating as a Github App",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_API_URL",
+            "Github API URL, e.g. for Github Enterprise (default: https://api.github.com)",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_APP_ID",
+            "Authenticate as this Github App instead of using GITHUB_TOKEN",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_APP_PRIVATE_KEY_PATH",
+            "Path to the PEM private key of the Github App",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_APP_INSTALLATION_ID",
+            "Installation of the Github App to use (default: first installation found)",
+            false,
+        ),
+    ]
+}
+
+struct CachedPage {
+    etag: String,
+    body: String,
+    next: Option<String>,
+    last_used: u64,
+}
+
+// Last response of each URL, 304 responses do not count against the quota
+#[derive(Default)]
+struct PageCache {
+    pages: HashMap<String, CachedPage>,
+    bytes: usize,
+    // Incremented on each access to order pages by last use
+    clock: u64,
+}
+
+impl PageCache {
+    fn etag(&mut self, url: &str) -> Option<String> {
+        self.clock += 1;
+        let page = self.pages.get_mut(url)?;
+        page.last_used = self.clock;
+        Some(page.etag.clone())
+    }
+
+    fn get(&self, url: &str) -> Option<(String, Option<String>)> {
+        let page = self.pages.get(url)?;
+        Some((page.body.clone(), page.next.clone()))
+    }
+
+    fn insert(&mut self, url: &str, etag: String, body: String, next: Option<String>) {
+        self.clock += 1;
+        let page = CachedPage {
+            etag,
+            body,
+            next,
+            last_used: self.clock,
+        };
+        self.bytes += page.body.len();
+        if let Some(old) = self.pages.insert(url.to_string(), page) {
+            self.bytes -= old.body.len();
+        }
+        while self.pages.len() > CACHE_MAX_PAGES || self.bytes > CACHE_MAX_BYTES {
+            let Some(oldest) = self
+                .pages
+                .iter()
+                .min_by_key(|(_, page)| page.last_used)
+                .map(|(url, _)| url.clone())
+            else {
+                break;
+            };
+            if let Some(evicted) = self.pages.remove(&oldest) {
+                self.bytes -= evicted.body.len();
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Credentials {
+    Token(String),
+    App(Arc<GithubApp>),
+}
+
+#[derive(Clone)]
+pub struct GithubClient {
+    api_url: String,
+    credentials: Credentials,
+    cache: Arc<Mutex<PageCache>>,
+}
+
+impl GithubClient {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let api_url = config::var("GITHUB_API_URL").unwrap_or(DEFAULT_API_URL.to_string());
+        let api_url = api_url.trim_end_matches('/').to_string();
+        let credentials = match config::var("GITHUB_APP_ID") {
+            Ok(app_id) => Credentials::App(GithubApp::shared(app_id, &api_url)?),
+            Err(_) => Credentials::Token(config::var("GITHUB_TOKEN")?),
+        };
+        Ok(GithubClient {
+            api_url,
+            credentials,
+            cache: Arc::new(Mutex::new(PageCache::default())),
+        })
+    }
+
+    async fn authorization(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let token = match &self.credentials {
+            Credentials::Token(token) => token.clone(),
+            Credentials::App(app) => app.installation_token().await?,
+        };
+        Ok(format!("Bearer {}", token))
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}{}", self.api_url, path);
+        let (body, _next) = self.get_page(&url).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    // Follows pagination through the Link header
+    pub async fn get_all<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Vec<T>, Box<dyn Error + Send + Sync>> {
+        let mut items = Vec::new();
+        let mut url = Some(format!("{}{}", self.api_url, path));
+        while let Some(page_url) = url {
+            let (body, next) = self.get_page(&page_url).await?;
+            let mut page: Vec<T> = serde_json::from_str(&body)?;
+            items.append(&mut page);
+            url = next;
+        }
+        Ok(items)
+    }
+
+    // Returns the data field, errors on some nodes are only logged as other nodes are still
+    // usable
+    pub async fn graphql<T: DeserializeOwned>(
+        &self,
+        query: &str,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        let url = self.graphql_url();
+        check_rate_limit(&url)?;
+        let response = request_agent()?
+            .post(&url)
+            .header("Authorization", self.authorization().await?)
+            .json(&json!({ "query": query }))
+            .send_measured()
+            .await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        log_quota(&headers);
+        let body = response.text().await?;
+        if !status.is_success() {
+            if is_rate_limited(&headers, &body) {
+                block(&headers);
+            }
+            return Err(format!("github: {} on {}: {}", status, url, body).into());
+        }
+        let response: GraphqlResponse<T> = serde_json::from_str(&body)?;
+        for error in response.errors.iter() {
+            debug!("github graphql: {}", error.message);
+        }
+        match response.data {
+            Some(data) => Ok(data),
+            None => Err(format!("github graphql: no data in response: {}", body).into()),
+        }
+    }
+
+    // https://api.github.com/graphql or https://<host>/api/graphql for Github Enterprise
+    fn graphql_url(&self) -> String {
+        match self.api_url.strip_suffix("/v3") {
+            Some(api_url) => format!("{}/graphql", api_url),
+            None => format!("{}/graphql", self.api_url),
+        }
+    }
+
+    // Body and next page URL
+    async fn get_page(
+        &self,
+        url: &str,
+    ) -> Result<(String, Option<String>), Box<dyn Error + Send + Sync>> {
+        check_rate_limit(url)?;
+        let etag = self
+            .cache
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .etag(url);
+        let mut request = request_agent()?
+            .get(url)
+            .header("Authorization", self.authorization().await?)
+            .header("Accept", "application/vnd.github+json");
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = request.send_measured().await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        log_quota(&headers);
+        match status {
+            StatusCode::NOT_MODIFIED => {
+                trace!("github: {} not modified", url);
+                let cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+                Ok(cache.get(url).ok_or("not modified page is not cached")?)
+            }
+            StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS => {
+                let body = response.text().await.unwrap_or_default();
+                if status == StatusCode::FORBIDDEN && !is_rate_limited(&headers, &body) {
+                    return Err(format!("github: {} on {}: {}", status, url, body).into());
+                }
+                block(&headers);
+                Err(format!("github rate limited on {}", url).into())
+            }
+            status if status.is_success() => {
+                let next = next_link(&headers);
+                let body = response.text().await?;
+                if let Some(etag) = headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+                    let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+                    cache.insert(url, etag.to_string(), body.clone(), next.clone());
+                }
+                Ok((body, next))
+            }
+            status => Err(format!("github: {} on {}", status, url).into()),
+        }
+    }
+}
+
+struct GithubApp {
+    app_id: String,
+    key: EncodingKey,
+    api_url: String,
+    state: AsyncMutex<GithubAppState>,
+}
+
+#[derive(Default)]
+struct GithubAppState {
+    installation_id: Option<u64>,
+    // Installation token and its expiration date
+    token: Option<(String, DateTime<Utc>)>,
+}
+
+#[derive(Serialize)]
+struct JwtClaims {
+    iat: u64,
+    exp: u64,
+    iss: String,
+}
+
+#[derive(Deserialize)]
+struct Installation {
+    id: u64,
+    account: Option<InstallationAccount>,
+}
+
+#[derive(Deserialize)]
+struct InstallationAccount {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct InstallationToken {
+    token: String,
+    expires_at: String,
+}
+
+impl GithubApp {
+    // All clients share the same installation token
+    fn shared(
+        app_id: String,
+        api_url: &str,
+    ) -> Result<Arc<GithubApp>, Box<dyn Error + Send + Sync>> {
+        static APP: OnceLock<Arc<GithubApp>> = OnceLock::new();
+        if let Some(app) = APP.get() {
+            return Ok(app.clone());
+        }
+        let key_path = config::var("GITHUB_APP_PRIVATE_KEY_PATH")
+            .map_err(|_| "GITHUB_APP_PRIVATE_KEY_PATH is required with GITHUB_APP_ID")?;
+        let pem = fs::read(&key_path)
+            .map_err(|err| format!("cannot read github app private key {}: {}", key_path, err))?;
+        let key = EncodingKey::from_rsa_pem(&pem)
+            .map_err(|err| format!("invalid github app private key {}: {}", key_path, err))?;
+        let installation_id = match config::var("GITHUB_APP_INSTALLATION_ID") {
+            Ok(installation_id) => Some(
+                installation_id
+                    .parse()
+                    .map_err(|err| format!("GITHUB_APP_INSTALLATION_ID: {}", err))?,
+            ),
+            Err(_) => None,
+        };
+        info!("authenticating as github app {}", app_id);
+        let app = GithubApp {
+            app_id,
+            key,
+            api_url: api_url.to_string(),
+            state: AsyncMutex::new(GithubAppState {
+                installation_id,
+                token: None,
+            }),
+        };
+        Ok(APP.get_or_init(|| Arc::new(app)).clone())
+    }
+
+    // Installation tokens expire after one hour, they are renewed a bit before
+    async fn installation_token(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut state = self.state.lock().await;
+        if let Some((token, expires_at)) = state.token.as_ref() {
+            if *expires_at - TOKEN_RENEWAL_MARGIN > Utc::now() {
+                return Ok(token.clone());
+            }
+        }
+        let jwt = self.jwt()?;
+        let installation_id = match state.installation_id {
+            Some(installation_id) => installation_id,
+            None => {
+                let installation_id = self.find_installation(&jwt).await?;
+                state.installation_id = Some(installation_id);
+                installation_id
+            }
+        };
+        let url = format!(
+            "{}/app/installations/{}/access_tokens",
+            self.api_url, installation_id
+        );
+        let response = request_agent()?
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", jwt))
+            .header("Accept", "application/vnd.github+json")
+            .send_measured()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(format!("github app: {} on {}: {}", status, url, body).into());
+        }
+        let token: InstallationToken = serde_json::from_str(&body)?;
+        let expires_at = DateTime::parse_from_rfc3339(&token.expires_at)?.with_timezone(&Utc);
+        debug!(
+            "github app: installation token renewed until {}",
+            expires_at
+        );
+        state.token = Some((token.token.clone(), expires_at));
+        Ok(token.token)
+    }
+
+    fn jwt(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        // Issued in the past to allow clock drift, Github refuses JWT valid more than 10 minutes
+        let claims = JwtClaims {
+            iat: now - 60,
+            exp: now + 9 * 60,
+            iss: self.app_id.clone(),
+        };
+        Ok(jsonwebtoken::encode(
+            &Header::new(Algorithm::RS256),
+            &claims,
+            &self.key,
+        )?)
+    }
+
+    async fn find_installation(&self, jwt: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/app/installations", self.api_url);
+        let response = request_agent()?
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", jwt))
+            .header("Accept", "application/vnd.github+json")
+            .send_measured()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(format!("github app: {} on {}: {}", status, url, body).into());
+        }
+        let installations: Vec<Installation> = serde_json::from_str(&body)?;
+        let installation = installations
+            .first()
+            .ok_or("github app is not installed anywhere")?;
+        if installations.len() > 1 {
+            warn!("github app: several installations found, set GITHUB_APP_INSTALLATION_ID");
+        }
+        let account = installation
+            .account
+            .as_ref()
+            .map(|account| account.login.as_str())
+            .unwrap_or("unknown account");
+        info!(
+            "github app: using installation {} on {}",
+            installation.id, account
+        );
+        Ok(installation.id)
+    }
+}
+
+#[derive(Deserialize)]
+struct GraphqlResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphqlError>,
+}
+
+#[derive(Deserialize)]
+struct GraphqlError {
+    message: String,
+}
+
+fn check_rate_limit(url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let blocked_until = *BLOCKED_UNTIL.lock().unwrap_or_else(|err| err.into_inner());
+    let now = Instant::now();
+    match blocked_until {
+        Some(blocked_until) if blocked_until > now => Err(format!(
+            "github rate limited for {}s, {} not requested",
+            (blocked_until - now).as_secs(),
+            url
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+fn block(headers: &HeaderMap) {
+    let backoff = backoff(headers);
+    warn!(
+        "github rate limit reached, backing off for {}s",
+        backoff.as_secs()
+    );
+    let mut lock = BLOCKED_UNTIL.lock().unwrap_or_else(|err| err.into_inner());
+    *lock = Some(Instant::now() + backoff);
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+// Primary rate limits exhaust the quota, secondary rate limits may come with Retry-After
+fn is_rate_limited(headers: &HeaderMap, body: &str) -> bool {
+    headers.contains_key(RETRY_AFTER)
+        || header_u64(headers, "x-ratelimit-remaining") == Some(0)
+        || body.contains("rate limit")
+}
+
+fn backoff(headers: &HeaderMap) -> Duration {
+    if let Some(retry_after) = header_u64(headers, RETRY_AFTER.as_str()) {
+        return Duration::from_secs(retry_after);
+    }
+    let reset = header_u64(headers, "x-ratelimit-reset");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match reset {
+        Some(reset) if reset > now => Duration::from_secs(reset - now),
+        _ => DEFAULT_BACKOFF,
+    }
+}
+
+fn log_quota(headers: &HeaderMap) {
+    let remaining = header_u64(headers, "x-ratelimit-remaining");
+    let limit = header_u64(headers, "x-ratelimit-limit");
+    let (Some(remaining), Some(limit)) = (remaining, limit) else {
+        return;
+    };
+    if (remaining as f64) < (limit as f64) * LOW_QUOTA_RATIO {
+        warn!(
+            "github quota is running low: {}/{} remaining",
+            remaining, limit
+        );
+    } else {
+        debug!("github quota: {}/{} remaining", remaining, limit);
+    }
+}
+
+// e.g. Link: <https://api.github.com/...&page=2>; rel="next", <...>; rel="last"
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    lazy_static! {
+        static ref NEXT: Regex = Regex::new(r#"<([^>]+)>;\s*rel="next""#).unwrap();
+    }
+    let link = headers.get(LINK)?.to_str().ok()?;
+    Some(NEXT.captures(link)?.get(1)?.as_str().to_string())
+}
@@ -0,0 +1,518 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::github::{self, GithubClient};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, TimeDelta, Timelike, Utc, Weekday};
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+const DEFAULT_REVIEW_DAYS: i64 = 3;
+const DEFAULT_STALE_DAYS: i64 = 30;
+const DEFAULT_DIGEST_HOUR: u32 = 9;
+const ITEMS_PER_PAGE: usize = 100;
+// Maximal number of items listed per category in the weekly digest
+const DIGEST_ITEMS_CNT: usize = 10;
+
+pub fn params() -> Vec<ModuleParam> {
+    [
+        github::params(),
+        vec![
+            ModuleParam::new(
+                "GITHUB_ACTIVITY_REPO_0_FULLNAME",
+                "Github repo whose issues and pull requests are watched, e.g. outscale/richard. Can be multiple (0..)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ACTIVITY_REVIEW_DAYS",
+                "Remind pull requests waiting for a review for more than this number of days (default: 3)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ACTIVITY_STALE_DAYS",
+                "Remind issues without update for more than this number of days (default: 30)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ACTIVITY_DIGEST_DAY",
+                "Post a weekly digest on this day (mon, tue, ...), no digest if not set",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ACTIVITY_DIGEST_HOUR",
+                "Hour (UTC) of the weekly digest (default: 9)",
+                false,
+            ),
+        ],
+    ]
+    .concat()
+}
+
+#[async_trait]
+impl Module for GithubActivity {
+    fn name(&self) -> &'static str {
+        "github_activity"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, variation: usize) -> Option<Vec<Message>> {
+        match variation {
+            0 => self.check_all_repos().await,
+            1 => self.digest().await,
+            var => {
+                error!("variation {var} is not managed");
+                None
+            }
+        }
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities::default()
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(3600), Duration::from_secs(600)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, _messages: &[Message]) {}
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let state = self.state.read().await;
+        serde_json::to_value(&*state).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let loaded: ActivityState = match serde_json::from_value(state) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                error!("cannot load github_activity state: {}", err);
+                return;
+            }
+        };
+        let mut state = self.state.write().await;
+        state.last_digest = loaded.last_digest;
+        for (full_name, repo) in loaded.repos {
+            // Repos removed from the configuration are forgotten
+            if let Some(known_repo) = state.repos.get_mut(&full_name) {
+                *known_repo = repo;
+            }
+        }
+    }
+}
+
+type RepoFullName = String;
+
+pub struct GithubActivity {
+    client: GithubClient,
+    review_days: i64,
+    stale_days: i64,
+    digest_day: Option<Weekday>,
+    digest_hour: u32,
+    state: RwLock<ActivityState>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct ActivityState {
+    repos: BTreeMap<RepoFullName, ActivityRepo>,
+    // ISO week of the last digest, e.g. 2024-W07
+    last_digest: Option<String>,
+}
+
+impl GithubActivity {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut state = ActivityState::default();
+        for i in 0..100 {
+            let Ok(full_name) = config::var(format!("GITHUB_ACTIVITY_REPO_{}_FULLNAME", i)) else {
+                break;
+            };
+            info!("github activity configured on repo {}", full_name);
+            state.repos.insert(full_name, ActivityRepo::default());
+        }
+        if state.repos.is_empty() {
+            warn!("github_activity module enabled but no repo configured");
+        }
+        let digest_day = match config::var("GITHUB_ACTIVITY_DIGEST_DAY") {
+            Ok(day) => Some(
+                day.parse::<Weekday>()
+                    .map_err(|_| format!("GITHUB_ACTIVITY_DIGEST_DAY: unknown day '{}'", day))?,
+            ),
+            Err(_) => None,
+        };
+        let digest_hour = match config::var("GITHUB_ACTIVITY_DIGEST_HOUR") {
+            Ok(hour) => match hour.parse() {
+                Ok(hour @ 0..=23) => hour,
+                _ => {
+                    return Err(
+                        format!("GITHUB_ACTIVITY_DIGEST_HOUR: invalid hour '{}'", hour).into(),
+                    )
+                }
+            },
+            Err(_) => DEFAULT_DIGEST_HOUR,
+        };
+        Ok(GithubActivity {
+            client: GithubClient::new()?,
+            review_days: days_var("GITHUB_ACTIVITY_REVIEW_DAYS", DEFAULT_REVIEW_DAYS)?,
+            stale_days: days_var("GITHUB_ACTIVITY_STALE_DAYS", DEFAULT_STALE_DAYS)?,
+            digest_day,
+            digest_hour,
+            state: RwLock::new(state),
+        })
+    }
+
+    async fn check_all_repos(&self) -> Option<Vec<Message>> {
+        let mut messages = Vec::new();
+        let mut state = self.state.write().await;
+        for (full_name, repo) in state.repos.iter_mut() {
+            messages.append(&mut repo.check(self, full_name).await);
+        }
+        if messages.is_empty() {
+            return None;
+        }
+        Some(messages)
+    }
+
+    async fn digest(&self) -> Option<Vec<Message>> {
+        let digest_day = self.digest_day?;
+        let now = Utc::now();
+        if now.weekday() != digest_day || now.hour() < self.digest_hour {
+            return None;
+        }
+        let week = now.format("%G-W%V").to_string();
+        let mut state = self.state.write().await;
+        if state.last_digest.as_ref() == Some(&week) {
+            return None;
+        }
+        // Activity is not persisted, wait for the first check after a restart
+        if state.repos.values().any(|repo| !repo.checked) {
+            return None;
+        }
+        trace!("posting github activity digest of {}", week);
+        state.last_digest = Some(week);
+        let messages = state
+            .repos
+            .iter()
+            .map(|(full_name, repo)| repo.digest_message(full_name, self))
+            .collect::<Vec<Message>>();
+        if messages.is_empty() {
+            return None;
+        }
+        Some(messages)
+    }
+}
+
+fn days_var(name: &str, default: i64) -> Result<i64, String> {
+    let Ok(days) = config::var(name) else {
+        return Ok(default);
+    };
+    match days.parse() {
+        Ok(days) if days > 0 => Ok(days),
+        _ => Err(format!("{}: invalid number of days '{}'", name, days)),
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct ActivityRepo {
+    // Issues and pull requests already seen, None until the first listing
+    known: Option<HashSet<u64>>,
+    review_reminded: HashSet<u64>,
+    stale_reminded: HashSet<u64>,
+    // Result of the last check, used by the digest
+    #[serde(skip)]
+    recent: Vec<Item>,
+    #[serde(skip)]
+    waiting_review: Vec<Item>,
+    #[serde(skip)]
+    stale: Vec<Item>,
+    #[serde(skip)]
+    checked: bool,
+}
+
+impl ActivityRepo {
+    async fn check(&mut self, activity: &GithubActivity, full_name: &str) -> Vec<Message> {
+        trace!("github activity: checking {}", full_name);
+        let mut messages = Vec::new();
+        let client = &activity.client;
+
+        let path = format!(
+            "/repos/{}/issues?state=open&sort=created&direction=desc&per_page={}",
+            full_name, ITEMS_PER_PAGE
+        );
+        match client.get::<Vec<Item>>(&path).await {
+            Ok(items) => messages.append(&mut self.new_items(full_name, items)),
+            Err(err) => error!("github activity: cannot list {} issues: {}", full_name, err),
+        };
+
+        // Oldest pull requests first
+        let path = format!(
+            "/repos/{}/pulls?state=open&sort=created&direction=asc&per_page={}",
+            full_name, ITEMS_PER_PAGE
+        );
+        match client.get::<Vec<Item>>(&path).await {
+            Ok(pulls) => {
+                let waiting_review = self
+                    .waiting_review(client, full_name, pulls, activity.review_days)
+                    .await;
+                for pull in waiting_review.iter() {
+                    if self.review_reminded.insert(pull.number) {
+                        messages.push(format!(
+                            "⏳ {} pull request [#{} {}]({}) by {} is waiting for a review since {} days",
+                            full_name,
+                            pull.number,
+                            pull.title,
+                            pull.html_url,
+                            pull.author(),
+                            pull.age_days()
+                        ));
+                    }
+                }
+                // Reviewed or closed pull requests may be reminded again later
+                self.review_reminded
+                    .retain(|number| waiting_review.iter().any(|pull| pull.number == *number));
+                self.waiting_review = waiting_review;
+            }
+            Err(err) => error!("github activity: cannot list {} pulls: {}", full_name, err),
+        };
+
+        // Least recently updated issues first
+        let path = format!(
+            "/repos/{}/issues?state=open&sort=updated&direction=asc&per_page={}",
+            full_name, ITEMS_PER_PAGE
+        );
+        match client.get::<Vec<Item>>(&path).await {
+            Ok(items) => {
+                let stale = items
+                    .into_iter()
+                    .filter(|item| !item.is_pull_request())
+                    .filter(|item| item.inactive_days() >= activity.stale_days)
+                    .collect::<Vec<Item>>();
+                // Items over the limit are reminded at the next check
+                let newly_stale = stale
+                    .iter()
+                    .filter(|item| !self.stale_reminded.contains(&item.number))
+                    .take(DIGEST_ITEMS_CNT)
+                    .collect::<Vec<&Item>>();
+                self.stale_reminded
+                    .extend(newly_stale.iter().map(|item| item.number));
+                let newly_stale = newly_stale
+                    .iter()
+                    .map(|item| {
+                        format!(
+                            "[#{} {}]({}) ({} days)",
+                            item.number,
+                            item.title,
+                            item.html_url,
+                            item.inactive_days()
+                        )
+                    })
+                    .collect::<Vec<String>>();
+                if !newly_stale.is_empty() {
+                    messages.push(format!(
+                        "💤 {} issue(s) without activity: {}",
+                        full_name,
+                        newly_stale.join(", ")
+                    ));
+                }
+                self.stale_reminded
+                    .retain(|number| stale.iter().any(|item| item.number == *number));
+                self.stale = stale;
+            }
+            Err(err) => error!("github activity: cannot list {} issues: {}", full_name, err),
+        };
+        self.checked = true;
+        messages
+    }
+
+    fn new_items(&mut self, full_name: &str, items: Vec<Item>) -> Vec<Message> {
+        let week_ago = Utc::now() - TimeDelta::days(7);
+        self.recent = items
+            .iter()
+            .filter(|item| item.created_at().is_some_and(|date| date > week_ago))
+            .cloned()
+            .collect();
+        let Some(known) = self.known.as_mut() else {
+            trace!(
+                "creating initial issue mapping for github repo {} with {} items",
+                full_name,
+                items.len()
+            );
+            self.known = Some(items.iter().map(|item| item.number).collect());
+            return Vec::new();
+        };
+        items
+            .iter()
+            .filter(|item| known.insert(item.number) && item.is_external())
+            .map(|item| {
+                format!(
+                    "🆕 {} {} [#{} {}]({}) opened by {} ({})",
+                    full_name,
+                    item.kind(),
+                    item.number,
+                    item.title,
+                    item.html_url,
+                    item.author(),
+                    item.contributor_kind()
+                )
+            })
+            .collect()
+    }
+
+    // Ready pull requests older than review_days without any review
+    async fn waiting_review(
+        &self,
+        client: &GithubClient,
+        full_name: &str,
+        pulls: Vec<Item>,
+        review_days: i64,
+    ) -> Vec<Item> {
+        let mut waiting_review = Vec::new();
+        for pull in pulls {
+            if pull.draft || pull.age_days() < review_days {
+                continue;
+            }
+            let path = format!("/repos/{}/pulls/{}/reviews", full_name, pull.number);
+            match client.get::<Vec<Review>>(&path).await {
+                Ok(reviews) if reviews.is_empty() => waiting_review.push(pull),
+                Ok(_) => {}
+                Err(err) => error!(
+                    "github activity: cannot get {} #{} reviews: {}",
+                    full_name, pull.number, err
+                ),
+            }
+        }
+        waiting_review
+    }
+
+    fn digest_message(&self, full_name: &str, activity: &GithubActivity) -> Message {
+        let list = |items: &[Item]| {
+            items
+                .iter()
+                .take(DIGEST_ITEMS_CNT)
+                .map(|item| format!("- [#{} {}]({})\n", item.number, item.title, item.html_url))
+                .collect::<String>()
+        };
+        let pulls = self
+            .recent
+            .iter()
+            .filter(|item| item.is_pull_request())
+            .count();
+        let mut message = format!(
+            "📊 Weekly digest of {}: {} issue(s) and {} pull request(s) opened during the last 7 days are still open\n",
+            full_name,
+            self.recent.len() - pulls,
+            pulls
+        );
+        if !self.waiting_review.is_empty() {
+            message.push_str(&format!(
+                "{} pull request(s) waiting for a review for more than {} days:\n{}",
+                self.waiting_review.len(),
+                activity.review_days,
+                list(&self.waiting_review)
+            ));
+        }
+        if !self.stale.is_empty() {
+            message.push_str(&format!(
+                "{} issue(s) without activity for more than {} days:\n{}",
+                self.stale.len(),
+                activity.stale_days,
+                list(&self.stale)
+            ));
+        }
+        message.trim_end().to_string()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Item {
+    number: u64,
+    title: String,
+    html_url: String,
+    user: Option<User>,
+    #[serde(default)]
+    author_association: String,
+    created_at: String,
+    updated_at: String,
+    #[serde(default)]
+    draft: bool,
+    // Only set on pull requests listed by the issues API
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Review {}
+
+impl Item {
+    fn is_pull_request(&self) -> bool {
+        self.pull_request.is_some()
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.is_pull_request() {
+            true => "pull request",
+            false => "issue",
+        }
+    }
+
+    fn author(&self) -> String {
+        match self.user.as_ref() {
+            Some(user) => format!("@{}", user.login),
+            None => "unknown".to_string(),
+        }
+    }
+
+    fn is_external(&self) -> bool {
+        !matches!(
+            self.author_association.as_str(),
+            "OWNER" | "MEMBER" | "COLLABORATOR"
+        )
+    }
+
+    fn contributor_kind(&self) -> &'static str {
+        match self.author_association.as_str() {
+            "FIRST_TIMER" => "first contribution on Github",
+            "FIRST_TIME_CONTRIBUTOR" => "first contribution",
+            "CONTRIBUTOR" => "contributor",
+            _ => "external",
+        }
+    }
+
+    fn created_at(&self) -> Option<DateTime<Utc>> {
+        Some(DateTime::parse_from_rfc3339(&self.created_at).ok()?.into())
+    }
+
+    fn age_days(&self) -> i64 {
+        days_since(&self.created_at)
+    }
+
+    fn inactive_days(&self) -> i64 {
+        days_since(&self.updated_at)
+    }
+}
+
+fn days_since(date: &str) -> i64 {
+    match DateTime::parse_from_rfc3339(date) {
+        Ok(date) => (Utc::now() - DateTime::<Utc>::from(date)).num_days(),
+        Err(_) => 0,
+    }
+}
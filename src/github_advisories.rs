@@ -0,0 +1,382 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::github::{self, GithubClient};
+use async_trait::async_trait;
+use log::{error, info, trace, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+const ADVISORIES_PER_PAGE: usize = 100;
+
+pub fn params() -> Vec<ModuleParam> {
+    [
+        github::params(),
+        vec![
+            ModuleParam::new(
+                "GITHUB_ADVISORIES_PACKAGE_0_ECOSYSTEM",
+                "Ecosystem of a watched package (rust, npm, pip, go, maven, rubygems, ...). Can be multiple (0..)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ADVISORIES_PACKAGE_0_NAME",
+                "Name of a watched package, e.g. tokio. Can be multiple (0..)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ADVISORIES_ORG_0_NAME",
+                "Github organisation whose repository advisories are watched, can be multiple (0..)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ADVISORIES_MIN_SEVERITY",
+                "Only announce advisories of this severity or above: low, medium, high, critical (default: low)",
+                false,
+            ),
+        ],
+    ]
+    .concat()
+}
+
+#[async_trait]
+impl Module for GithubAdvisories {
+    fn name(&self) -> &'static str {
+        "github_advisories"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        self.check_all_sources().await
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities::default()
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(3600)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, _messages: &[Message]) {}
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let sources = self.sources.read().await;
+        let known = sources
+            .iter()
+            .filter_map(|(key, source)| Some((key.clone(), source.known.clone()?)))
+            .collect::<BTreeMap<SourceKey, HashSet<GhsaId>>>();
+        serde_json::to_value(known).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let known: BTreeMap<SourceKey, HashSet<GhsaId>> = match serde_json::from_value(state) {
+            Ok(known) => known,
+            Err(err) => {
+                error!("cannot load github_advisories state: {}", err);
+                return;
+            }
+        };
+        let mut sources = self.sources.write().await;
+        for (key, known) in known {
+            // Packages and organisations removed from the configuration are forgotten
+            if let Some(source) = sources.get_mut(&key) {
+                source.known = Some(known);
+            }
+        }
+    }
+}
+
+// e.g. "rust/tokio" for a package or "org/outscale" for an organisation
+type SourceKey = String;
+type GhsaId = String;
+
+pub struct GithubAdvisories {
+    client: GithubClient,
+    min_severity: Severity,
+    sources: RwLock<BTreeMap<SourceKey, AdvisorySource>>,
+}
+
+impl GithubAdvisories {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut sources = BTreeMap::new();
+        for i in 0..100 {
+            let Ok(ecosystem) = config::var(format!("GITHUB_ADVISORIES_PACKAGE_{}_ECOSYSTEM", i))
+            else {
+                break;
+            };
+            let name_var = format!("GITHUB_ADVISORIES_PACKAGE_{}_NAME", i);
+            let name = config::var(&name_var)
+                .map_err(|_| format!("{}: missing package name", name_var))?;
+            info!(
+                "github advisories configured on {} package {}",
+                ecosystem, name
+            );
+            let ecosystem = ecosystem.to_lowercase();
+            sources.insert(
+                format!("{}/{}", ecosystem, name),
+                AdvisorySource::package(ecosystem, name),
+            );
+        }
+        for i in 0..100 {
+            let Ok(org_name) = config::var(format!("GITHUB_ADVISORIES_ORG_{}_NAME", i)) else {
+                break;
+            };
+            info!("github advisories configured on organisation {}", org_name);
+            sources.insert(format!("org/{}", org_name), AdvisorySource::org(&org_name));
+        }
+        if sources.is_empty() {
+            warn!("github_advisories module enabled but no package or organisation configured");
+        }
+        let min_severity = match config::var("GITHUB_ADVISORIES_MIN_SEVERITY") {
+            Ok(severity) => severity.parse().map_err(|_| {
+                format!(
+                    "GITHUB_ADVISORIES_MIN_SEVERITY: unknown severity '{}'",
+                    severity
+                )
+            })?,
+            Err(_) => Severity::Low,
+        };
+        Ok(GithubAdvisories {
+            client: GithubClient::new()?,
+            min_severity,
+            sources: RwLock::new(sources),
+        })
+    }
+
+    async fn check_all_sources(&self) -> Option<Vec<Message>> {
+        let mut messages = Vec::new();
+        // The same advisory can affect several watched packages
+        let mut announced = HashSet::new();
+        let mut sources = self.sources.write().await;
+        for (key, source) in sources.iter_mut() {
+            trace!("github advisories: checking {}", key);
+            let advisories = match self.client.get::<Vec<Advisory>>(&source.path).await {
+                Ok(advisories) => advisories,
+                Err(err) => {
+                    error!("github advisories: cannot list {} advisories: {}", key, err);
+                    continue;
+                }
+            };
+            for advisory in source.new_advisories(key, advisories) {
+                let severity = advisory.severity();
+                if severity != Severity::Unknown && severity < self.min_severity {
+                    continue;
+                }
+                if announced.insert(advisory.ghsa_id.clone()) {
+                    messages.push(advisory.build_message(source.package.as_ref()));
+                }
+            }
+        }
+        if messages.is_empty() {
+            return None;
+        }
+        Some(messages)
+    }
+}
+
+struct AdvisorySource {
+    path: String,
+    // Only set for global advisories, used to show the matching vulnerabilities
+    package: Option<Package>,
+    // Advisories already seen, None until the first listing
+    known: Option<HashSet<GhsaId>>,
+}
+
+impl AdvisorySource {
+    fn package(ecosystem: String, name: String) -> Self {
+        // Names like @scope/pkg (npm) or group:artifact (maven) need encoding
+        let query = [
+            ("ecosystem", ecosystem.as_str()),
+            ("affects", name.as_str()),
+            ("sort", "published"),
+            ("direction", "desc"),
+            ("per_page", &ADVISORIES_PER_PAGE.to_string()),
+        ];
+        let query = reqwest::Url::parse_with_params("https://localhost/advisories", &query)
+            .ok()
+            .and_then(|url| url.query().map(|query| query.to_string()))
+            .unwrap_or_default();
+        AdvisorySource {
+            path: format!("/advisories?{}", query),
+            package: Some(Package {
+                ecosystem,
+                name: Some(name),
+            }),
+            known: None,
+        }
+    }
+
+    fn org(org_name: &str) -> Self {
+        AdvisorySource {
+            path: format!(
+                "/orgs/{}/security-advisories?state=published&sort=published&direction=desc&per_page={}",
+                org_name, ADVISORIES_PER_PAGE
+            ),
+            package: None,
+            known: None,
+        }
+    }
+
+    fn new_advisories(&mut self, key: &str, advisories: Vec<Advisory>) -> Vec<Advisory> {
+        let Some(known) = self.known.as_mut() else {
+            trace!(
+                "creating initial advisory mapping for {} with {} advisories",
+                key,
+                advisories.len()
+            );
+            self.known = Some(advisories.into_iter().map(|a| a.ghsa_id).collect());
+            return Vec::new();
+        };
+        advisories
+            .into_iter()
+            .filter(|advisory| known.insert(advisory.ghsa_id.clone()))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl FromStr for Severity {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "low" => Ok(Severity::Low),
+            "medium" | "moderate" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (emoji, name) = match self {
+            Severity::Unknown => ("⚪", "unknown"),
+            Severity::Low => ("🟢", "low"),
+            Severity::Medium => ("🟡", "medium"),
+            Severity::High => ("🟠", "high"),
+            Severity::Critical => ("🔴", "critical"),
+        };
+        write!(f, "{} {}", emoji, name)
+    }
+}
+
+#[derive(Deserialize)]
+struct Advisory {
+    ghsa_id: String,
+    cve_id: Option<String>,
+    html_url: String,
+    summary: String,
+    severity: Option<String>,
+    #[serde(default)]
+    vulnerabilities: Vec<Vulnerability>,
+}
+
+#[derive(Deserialize)]
+struct Vulnerability {
+    package: Option<Package>,
+    vulnerable_version_range: Option<String>,
+    // Global advisories
+    first_patched_version: Option<String>,
+    // Repository advisories
+    patched_versions: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    ecosystem: String,
+    name: Option<String>,
+}
+
+impl Package {
+    fn matches(&self, other: &Package) -> bool {
+        // Some registries like pip or NuGet have case insensitive names
+        let name = self.name.as_deref().map(str::to_lowercase);
+        self.ecosystem.eq_ignore_ascii_case(&other.ecosystem)
+            && name == other.name.as_deref().map(str::to_lowercase)
+    }
+}
+
+impl Advisory {
+    fn severity(&self) -> Severity {
+        self.severity
+            .as_deref()
+            .and_then(|severity| severity.parse().ok())
+            .unwrap_or(Severity::Unknown)
+    }
+
+    fn build_message(&self, package: Option<&Package>) -> Message {
+        let id = match self.cve_id.as_ref() {
+            Some(cve_id) => format!("{} {}", self.ghsa_id, cve_id),
+            None => self.ghsa_id.clone(),
+        };
+        // Repository advisory urls look like https://github.com/<owner>/<repo>/security/advisories/<id>
+        let repo = match self.html_url.split_once("/security/advisories/") {
+            Some((repo_url, _)) => {
+                let full_name = repo_url.splitn(4, '/').nth(3).unwrap_or(repo_url);
+                format!(" in {}", full_name)
+            }
+            None => String::new(),
+        };
+        let mut message = format!(
+            "🛡️ {} advisory [{}]({}){}: {}",
+            self.severity(),
+            id,
+            self.html_url,
+            repo,
+            self.summary
+        );
+        for vulnerability in self.vulnerabilities.iter() {
+            let affected = match (vulnerability.package.as_ref(), package) {
+                (Some(affected), Some(package)) if !affected.matches(package) => continue,
+                (Some(affected), _) => affected,
+                (None, _) => continue,
+            };
+            let patched = vulnerability
+                .first_patched_version
+                .as_ref()
+                .or(vulnerability.patched_versions.as_ref())
+                .filter(|patched| !patched.is_empty())
+                .map(|patched| format!("patched in {}", patched))
+                .unwrap_or("no patched version".to_string());
+            message.push_str(&format!(
+                "\n- {}/{} {}: {}",
+                affected.ecosystem,
+                affected.name.as_deref().unwrap_or("unknown"),
+                vulnerability
+                    .vulnerable_version_range
+                    .as_deref()
+                    .unwrap_or("all versions"),
+                patched
+            ));
+        }
+        message
+    }
+}
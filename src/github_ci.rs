@@ -0,0 +1,426 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::github::{self, GithubClient};
+use crate::github_orgs;
+use async_trait::async_trait;
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+const RUNS_PER_PAGE: usize = 50;
+
+pub fn params() -> Vec<ModuleParam> {
+    [
+        github::params(),
+        vec![
+            ModuleParam::new(
+                "GITHUB_CI_REPO_0_FULLNAME",
+                "Github repo whose workflows are watched, e.g. outscale/richard. Can be multiple (0..)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_CI_ORG_0_NAME",
+                "Github organisation whose repos workflows are watched, can be multiple (0..)",
+                false,
+            ),
+        ],
+    ]
+    .concat()
+}
+
+#[async_trait]
+impl Module for GithubCi {
+    fn name(&self) -> &'static str {
+        "github_ci"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, variation: usize) -> Option<Vec<Message>> {
+        match variation {
+            0 => self.check_all_repos().await,
+            1 => {
+                self.update_org_repos().await;
+                None
+            }
+            var => {
+                error!("variation {var} is not managed");
+                None
+            }
+        }
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities::default()
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        let day_s = 60 * 60 * 24;
+        vec![Duration::from_secs(300), Duration::from_secs(day_s)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, _messages: &[Message]) {}
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let repos = self.repos.read().await;
+        let workflows = repos
+            .iter()
+            .map(|(full_name, repo)| (full_name.clone(), repo.workflows.clone()))
+            .collect::<HashMap<RepoFullName, HashMap<WorkflowId, Workflow>>>();
+        serde_json::to_value(workflows).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let workflows: HashMap<RepoFullName, HashMap<WorkflowId, Workflow>> =
+            match serde_json::from_value(state) {
+                Ok(workflows) => workflows,
+                Err(err) => {
+                    error!("cannot load github_ci state: {}", err);
+                    return;
+                }
+            };
+        let mut repos = self.repos.write().await;
+        for (full_name, workflows) in workflows {
+            repos.entry(full_name).or_default().workflows = workflows;
+        }
+    }
+}
+
+type RepoFullName = String;
+// Workflow ids are kept as strings to be usable as JSON keys
+type WorkflowId = String;
+
+pub struct GithubCi {
+    client: GithubClient,
+    orgs: Vec<String>,
+    repos: RwLock<HashMap<RepoFullName, CiRepo>>,
+}
+
+impl GithubCi {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut repos = HashMap::new();
+        for i in 0..100 {
+            let Ok(full_name) = config::var(format!("GITHUB_CI_REPO_{}_FULLNAME", i)) else {
+                break;
+            };
+            info!("github ci configured on repo {}", full_name);
+            let repo = CiRepo {
+                configured: true,
+                ..CiRepo::default()
+            };
+            repos.insert(full_name, repo);
+        }
+        let mut orgs = Vec::new();
+        for i in 0..100 {
+            let Ok(org_name) = config::var(format!("GITHUB_CI_ORG_{}_NAME", i)) else {
+                break;
+            };
+            info!("github ci configured on organisation {}", org_name);
+            orgs.push(org_name);
+        }
+        if repos.is_empty() && orgs.is_empty() {
+            warn!("github_ci module enabled but no repo or organisation configured");
+        }
+        Ok(GithubCi {
+            client: GithubClient::new()?,
+            orgs,
+            repos: RwLock::new(repos),
+        })
+    }
+
+    async fn check_all_repos(&self) -> Option<Vec<Message>> {
+        let org_repos_listed = self.repos.read().await.values().any(|repo| repo.from_org);
+        if !self.orgs.is_empty() && !org_repos_listed {
+            self.update_org_repos().await;
+        }
+        let targets = self
+            .repos
+            .read()
+            .await
+            .iter()
+            .map(|(full_name, repo)| (full_name.clone(), repo.details.clone()))
+            .collect::<Vec<(RepoFullName, Option<RepoDetails>)>>();
+        let mut messages = Vec::new();
+        for (full_name, details) in targets {
+            // Requests are made without holding the lock, results are applied per repo
+            let result = fetch_runs(&self.client, &full_name, details).await;
+            let mut repos = self.repos.write().await;
+            // The repo may have left the organisation meanwhile
+            let Some(repo) = repos.get_mut(&full_name) else {
+                continue;
+            };
+            match result {
+                Some((details, runs)) => {
+                    messages.append(&mut repo.update(&full_name, details, &runs));
+                }
+                // Details may be outdated, e.g. after a default branch rename
+                None => repo.details = None,
+            }
+        }
+        if messages.is_empty() {
+            return None;
+        }
+        Some(messages)
+    }
+
+    async fn update_org_repos(&self) {
+        let mut listed = HashSet::new();
+        let mut failed_orgs = Vec::new();
+        for org_name in self.orgs.iter() {
+            match github_orgs::get_all_org_repos(&self.client, org_name).await {
+                Ok(full_names) => listed.extend(full_names),
+                Err(err) => {
+                    error!("github ci: cannot list {} repos: {}", org_name, err);
+                    failed_orgs.push(org_name.to_lowercase());
+                }
+            };
+        }
+        let mut repos = self.repos.write().await;
+        // Deleted, archived or transferred repos are forgotten, repos of organisations which
+        // could not be listed are kept until the next listing
+        repos.retain(|full_name, repo| {
+            let owner = full_name.split('/').next().unwrap_or_default();
+            let keep = repo.configured
+                || listed.contains(full_name)
+                || failed_orgs.contains(&owner.to_lowercase());
+            if !keep {
+                debug!("github ci: {} is not watched anymore", full_name);
+            }
+            // Default branch and archival are read again on the next check
+            repo.details = None;
+            keep
+        });
+        for full_name in listed {
+            // Keep already known repos so their workflow states are not lost
+            repos.entry(full_name).or_default().from_org = true;
+        }
+    }
+}
+
+// Repo details and completed runs on the default branch, no run is fetched for archived repos
+async fn fetch_runs(
+    client: &GithubClient,
+    full_name: &str,
+    details: Option<RepoDetails>,
+) -> Option<(RepoDetails, Vec<WorkflowRun>)> {
+    trace!("github ci: checking {}", full_name);
+    let details = match details {
+        Some(details) => details,
+        None => match client
+            .get::<RepoDetails>(&format!("/repos/{}", full_name))
+            .await
+        {
+            Ok(details) => details,
+            Err(err) => {
+                error!("github ci: cannot read repo {}: {}", full_name, err);
+                return None;
+            }
+        },
+    };
+    if details.archived {
+        return Some((details, Vec::new()));
+    }
+    let path = format!(
+        "/repos/{}/actions/runs?branch={}&exclude_pull_requests=true&per_page={}",
+        full_name, details.default_branch, RUNS_PER_PAGE
+    );
+    match client.get::<WorkflowRuns>(&path).await {
+        Ok(runs) => Some((details, runs.workflow_runs)),
+        Err(err) => {
+            error!("github ci: cannot get {} workflow runs: {}", full_name, err);
+            None
+        }
+    }
+}
+
+#[derive(Default)]
+struct CiRepo {
+    details: Option<RepoDetails>,
+    workflows: HashMap<WorkflowId, Workflow>,
+    from_org: bool,
+    // Explicitly configured repos are kept even if they leave a watched organisation
+    configured: bool,
+}
+
+impl CiRepo {
+    fn update(
+        &mut self,
+        full_name: &str,
+        details: RepoDetails,
+        runs: &[WorkflowRun],
+    ) -> Vec<Message> {
+        let details = self.details.insert(details);
+        let mut latest_runs = HashMap::<WorkflowId, &WorkflowRun>::new();
+        for run in runs.iter().filter(|run| run.status == "completed") {
+            let latest = latest_runs
+                .entry(run.workflow_id.to_string())
+                .or_insert(run);
+            if run.run_number > latest.run_number {
+                *latest = run;
+            }
+        }
+        let mut messages = Vec::new();
+        for (workflow_id, run) in latest_runs {
+            let Some(failing) = run.is_failure() else {
+                continue;
+            };
+            let known = self.workflows.contains_key(&workflow_id);
+            let workflow = self.workflows.entry(workflow_id).or_default();
+            if workflow.last_run_id == Some(run.id) {
+                continue;
+            }
+            let failing_change = workflow.update(run, failing);
+            // Only record the status of workflows seen for the first time
+            if !known {
+                trace!(
+                    "github ci: initial status of {} workflow {}: failing={}",
+                    full_name,
+                    workflow.name,
+                    failing
+                );
+                continue;
+            }
+            if let Some(message) = run.build_status_message(full_name, details, failing_change) {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Workflow {
+    name: String,
+    failing: bool,
+    last_run_id: Option<u64>,
+}
+
+impl Workflow {
+    fn update(&mut self, run: &WorkflowRun, failing: bool) -> (bool, bool) {
+        let failing_old = self.failing;
+        self.name = run.name.clone();
+        self.failing = failing;
+        self.last_run_id = Some(run.id);
+        if failing_old != failing {
+            debug!(
+                "github ci: workflow {} failing went from {} to {}",
+                self.name, failing_old, failing
+            );
+        }
+        (failing_old, failing)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct RepoDetails {
+    default_branch: String,
+    #[serde(default)]
+    archived: bool,
+}
+
+#[derive(Deserialize)]
+struct WorkflowRuns {
+    workflow_runs: Vec<WorkflowRun>,
+}
+
+#[derive(Deserialize)]
+struct WorkflowRun {
+    id: u64,
+    workflow_id: u64,
+    name: String,
+    run_number: u64,
+    status: String,
+    conclusion: Option<String>,
+    html_url: String,
+    head_sha: String,
+    head_commit: Option<HeadCommit>,
+    actor: Option<Actor>,
+}
+
+#[derive(Deserialize)]
+struct HeadCommit {
+    message: String,
+    author: Option<CommitAuthor>,
+}
+
+#[derive(Deserialize)]
+struct CommitAuthor {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Actor {
+    login: String,
+}
+
+impl WorkflowRun {
+    // None for conclusions which do not tell if the workflow is broken (e.g. cancelled)
+    fn is_failure(&self) -> Option<bool> {
+        match self.conclusion.as_deref()? {
+            "success" => Some(false),
+            "failure" | "timed_out" | "startup_failure" => Some(true),
+            _ => None,
+        }
+    }
+
+    fn build_status_message(
+        &self,
+        full_name: &str,
+        details: &RepoDetails,
+        failing_change: (bool, bool),
+    ) -> Option<String> {
+        match failing_change {
+            (false, true) => {
+                let short_sha = &self.head_sha[..self.head_sha.len().min(7)];
+                let title = self
+                    .head_commit
+                    .as_ref()
+                    .and_then(|commit| commit.message.lines().next())
+                    .unwrap_or_default();
+                let author = self
+                    .head_commit
+                    .as_ref()
+                    .and_then(|commit| commit.author.as_ref())
+                    .map(|author| author.name.clone())
+                    .or(self.actor.as_ref().map(|actor| actor.login.clone()))
+                    .unwrap_or("unknown".to_string());
+                // html_url looks like https://github.com/<owner>/<repo>/actions/runs/<id>
+                let repo_url = self.html_url.split("/actions/").next().unwrap_or_default();
+                Some(format!(
+                    "❌ {} workflow **{}** failed on {}: [{}]({}/commit/{}) {} by {} ([run #{}]({}))",
+                    full_name,
+                    self.name,
+                    details.default_branch,
+                    short_sha,
+                    repo_url,
+                    self.head_sha,
+                    title,
+                    author,
+                    self.run_number,
+                    self.html_url
+                ))
+            }
+            (true, false) => Some(format!(
+                "✅ {} workflow **{}** is green again on {} ([run #{}]({}))",
+                full_name, self.name, details.default_branch, self.run_number, self.html_url
+            )),
+            _ => None,
+        }
+    }
+}
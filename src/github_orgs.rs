@@ -1,19 +1,66 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
-use crate::github_repos::{self, GithubRepo};
-use crate::utils::request_agent;
+use crate::config;
+use crate::github::GithubClient;
+use crate::github_repos::{self, GithubRepo, ReleaseFilter, ReleaseFormat, ReleaseId};
 use async_trait::async_trait;
 use log::{debug, error, info, trace, warn};
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::env;
-use std::env::VarError;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use tokio::sync::RwLock;
 use tokio::time::Duration;
 
 const DEFAULT_ITEM_PER_PAGE: usize = 100;
+const DEFAULT_GRAPHQL_BATCH_SIZE: usize = 50;
+// GraphQL queries are limited to 500,000 nodes, each repo requests up to 20 releases * 100 assets
+const MAX_GRAPHQL_BATCH_SIZE: usize = 100;
+
+pub fn params() -> Vec<ModuleParam> {
+    [
+        github_repos::params(),
+        vec![
+            ModuleParam::new(
+                "GITHUB_ORG_0_NAME",
+                "Github organisation name, can be multiple (0..)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_GRAPHQL_BATCH_SIZE",
+                "Number of organisation repos checked per GraphQL query, 0 to use REST API only (default: 50, max: 100)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ORG_0_PRERELEASES",
+                "Announce prereleases of the organisation repos (default: true)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ORG_0_TAG_INCLUDE",
+                "Only announce releases whose tag matches this regex (e.g. ^v)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ORG_0_TAG_EXCLUDE",
+                "Don't announce releases whose tag matches this regex (e.g. ^nightly-)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ORG_0_SEMVER",
+                "Only announce 'major' (X.0.0) or 'minor' (X.Y.0) releases (default: all)",
+                false,
+            ),
+            ModuleParam::new(
+                "GITHUB_ORG_0_MAX_AGE_DAYS",
+                "Don't announce releases published more than this number of days ago (default: 10)",
+                false,
+            ),
+        ],
+    ]
+    .concat()
+}
 
 #[async_trait]
 impl Module for GithubOrgs {
@@ -21,30 +68,22 @@ impl Module for GithubOrgs {
         "github_orgs"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        [
-            github_repos::params(),
-            vec![
-                ModuleParam::new("GITHUB_TOKEN", "Github token to make api calls", true),
-                ModuleParam::new(
-                    "GITHUB_ORG_0_NAME",
-                    "Github organisation name, can be multiple (0..)",
-                    false,
-                ),
-            ],
-        ]
-        .concat()
+    async fn module_offering(&self, modules: &[ModuleData]) {
+        if !modules.iter().any(|module| module.name == "github_repos") {
+            return;
+        }
+        // Releases of repos also watched by github_repos are only announced once, by github_repos
+        let mut watched_by_repos = self.watched_by_repos.write().await;
+        *watched_by_repos = github_repos::configured_repos()
+            .iter()
+            .map(|full_name| full_name.to_lowercase())
+            .collect();
     }
 
-    async fn module_offering(&self, _modules: &[ModuleData]) {}
-
     async fn run(&self, variation: usize) -> Option<Vec<Message>> {
         match variation {
             0 => self.run_all_repos().await,
-            1 => {
-                self.update_repo_listing().await;
-                None
-            }
+            1 => self.update_repo_listing().await,
             _ => {
                 error!("bad variation run()");
                 None
@@ -72,20 +111,58 @@ impl Module for GithubOrgs {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let orgs = self.orgs.read().await;
+        let state = OrgsState {
+            releases: orgs
+                .iter()
+                .map(|org| (org.name.clone(), org.known_releases()))
+                .collect(),
+            listings: orgs
+                .iter()
+                .filter_map(|org| Some((org.name.clone(), org.listing.clone()?)))
+                .collect(),
+        };
+        serde_json::to_value(state).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let mut loaded: OrgsState = match serde_json::from_value(state) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                error!("cannot load github_orgs state: {}", err);
+                return;
+            }
+        };
+        let mut orgs = self.orgs.write().await;
+        for org in orgs.iter_mut() {
+            if let Some(releases) = loaded.releases.remove(&org.name) {
+                org.set_known_releases(releases);
+            }
+            if let Some(listing) = loaded.listings.remove(&org.name) {
+                org.listing = Some(listing);
+            }
+        }
+    }
 }
 pub struct GithubOrgs {
     orgs: RwLock<Vec<GithubOrg>>,
+    // Lowercase full names of repos whose releases are announced by github_repos
+    watched_by_repos: RwLock<HashSet<RepoFullName>>,
 }
 
 impl GithubOrgs {
-    pub fn new() -> Result<Self, VarError> {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
         let mut orgs: Vec<GithubOrg> = Vec::new();
+        let format = ReleaseFormat::from_config()?;
         for i in 0..100 {
-            let org_name = env::var(format!("GITHUB_ORG_{}_NAME", i));
+            let org_name = config::var(format!("GITHUB_ORG_{}_NAME", i));
             match org_name {
                 Ok(org_name) => {
                     info!("github organisation configured: {}", org_name);
-                    let new_org = GithubOrg::new(org_name.as_str())?;
+                    let filter = ReleaseFilter::from_config(&format!("GITHUB_ORG_{}", i))?;
+                    let new_org = GithubOrg::new(org_name.as_str(), filter, format.clone())?;
                     orgs.push(new_org);
                 }
                 _ => break,
@@ -96,15 +173,17 @@ impl GithubOrgs {
         }
         Ok(GithubOrgs {
             orgs: RwLock::new(orgs),
+            watched_by_repos: RwLock::new(HashSet::new()),
         })
     }
 
     async fn run_all_repos(&self) -> Option<Vec<Message>> {
         let mut all_messages = Vec::new();
+        let watched_by_repos = self.watched_by_repos.read().await;
         let mut orgs = self.orgs.write().await;
         for org in orgs.iter_mut() {
             trace!("run on org {}...", org.name);
-            if let Some(mut messages) = org.run().await {
+            if let Some(mut messages) = org.run(&watched_by_repos).await {
                 all_messages.append(&mut messages);
             }
         }
@@ -114,41 +193,95 @@ impl GithubOrgs {
         Some(all_messages)
     }
 
-    async fn update_repo_listing(&self) {
+    async fn update_repo_listing(&self) -> Option<Vec<Message>> {
+        let mut all_messages = Vec::new();
         let mut orgs = self.orgs.write().await;
         for org in orgs.iter_mut() {
             trace!("update repo listing for org {}", org.name);
-            org.update_repo_listing().await;
+            all_messages.append(&mut org.update_repo_listing().await);
         }
+        if all_messages.is_empty() {
+            return None;
+        }
+        Some(all_messages)
     }
 }
 
+type OrgName = String;
 type RepoFullName = String;
+type RepoId = String;
+
+#[derive(Deserialize, Serialize)]
+struct OrgsState {
+    releases: HashMap<OrgName, HashMap<RepoFullName, HashSet<ReleaseId>>>,
+    listings: HashMap<OrgName, HashMap<RepoId, OrgRepo>>,
+}
 
 #[derive(Clone)]
 struct GithubOrg {
     name: String,
     repos: HashMap<RepoFullName, GithubRepo>,
-    github_token: String,
+    // Last repo listing by repo id, used to detect new, renamed, archived and removed repos
+    listing: Option<HashMap<RepoId, OrgRepo>>,
+    // Release filtering rules applied to all repos of the organisation
+    filter: ReleaseFilter,
+    format: ReleaseFormat,
+    client: GithubClient,
+    graphql_batch_size: usize,
 }
 
 impl GithubOrg {
-    fn new(org_name: &str) -> Result<Self, VarError> {
+    fn new(
+        org_name: &str,
+        filter: ReleaseFilter,
+        format: ReleaseFormat,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         Ok(GithubOrg {
             name: org_name.into(),
             repos: HashMap::new(),
-            github_token: env::var("GITHUB_TOKEN")?,
+            listing: None,
+            filter,
+            format,
+            client: GithubClient::new()?,
+            graphql_batch_size: graphql_batch_size()?,
         })
     }
 
-    async fn run(&mut self) -> Option<Vec<Message>> {
+    async fn run(&mut self, watched_by_repos: &HashSet<RepoFullName>) -> Option<Vec<Message>> {
+        let mut all_messages = Vec::new();
         if self.repos.is_empty() {
-            self.update_repo_listing().await;
+            all_messages.append(&mut self.update_repo_listing().await);
         }
-        let mut all_messages = Vec::new();
-        for (_full_name, repo) in self.repos.iter_mut() {
-            if let Some(mut messages) = repo.run().await {
-                all_messages.append(&mut messages);
+        let mut full_names = self
+            .repos
+            .keys()
+            .filter(|full_name| !watched_by_repos.contains(&full_name.to_lowercase()))
+            .cloned()
+            .collect::<Vec<RepoFullName>>();
+        full_names.sort();
+        let batch_size = self.graphql_batch_size.max(1);
+        for batch in full_names.chunks(batch_size) {
+            let mut batched = HashMap::new();
+            if self.graphql_batch_size > 0 {
+                match github_repos::batch_releases(&self.client, batch).await {
+                    Ok(result) => batched = result,
+                    Err(err) => warn!(
+                        "{} org: graphql batch failed, falling back to REST: {}",
+                        self.name, err
+                    ),
+                };
+            }
+            for full_name in batch {
+                let Some(repo) = self.repos.get_mut(full_name) else {
+                    continue;
+                };
+                let messages = match batched.remove(full_name) {
+                    Some(batched_repo) => repo.run_batched(batched_repo),
+                    None => repo.run().await,
+                };
+                if let Some(mut messages) = messages {
+                    all_messages.append(&mut messages);
+                }
             }
         }
         if all_messages.is_empty() {
@@ -157,103 +290,212 @@ impl GithubOrg {
         Some(all_messages)
     }
 
-    async fn update_repo_listing(&mut self) {
-        let repos = match self.get_all_org_repos().await {
-            Ok(repos) => repos,
+    fn known_releases(&self) -> HashMap<RepoFullName, HashSet<ReleaseId>> {
+        self.repos
+            .iter()
+            .filter_map(|(full_name, repo)| Some((full_name.clone(), repo.known_releases()?)))
+            .collect()
+    }
+
+    fn set_known_releases(&mut self, known_releases: HashMap<RepoFullName, HashSet<ReleaseId>>) {
+        for (full_name, releases) in known_releases {
+            let mut repo =
+                match GithubRepo::new(full_name.as_str(), self.filter.clone(), self.format.clone())
+                {
+                    Ok(repo) => repo,
+                    Err(err) => {
+                        error!("cannot create GithubRepo: {:#?}", err);
+                        continue;
+                    }
+                };
+            repo.set_known_releases(releases);
+            self.repos.insert(full_name, repo);
+        }
+    }
+
+    async fn update_repo_listing(&mut self) -> Vec<Message> {
+        let listing = match list_org_repos(&self.client, &self.name).await {
+            Ok(repos) => repos
+                .into_iter()
+                .map(|repo| (repo.id.to_string(), repo))
+                .collect::<HashMap<RepoId, OrgRepo>>(),
             Err(err) => {
                 error!("cannot fetch all org repos: {:#?}", err);
-                return;
+                return Vec::new();
             }
         };
-        for repo in repos {
-            let full_name = repo.full_name;
-            let repo = match GithubRepo::new(full_name.as_str()) {
-                Ok(repo) => repo,
-                Err(err) => {
-                    error!("cannot create GithubRepo: {:#?}", err);
-                    continue;
-                }
-            };
+        let mut messages = Vec::new();
+        if let Some(previous) = self.listing.take() {
+            messages = self.listing_changes(&previous, &listing).await;
+        }
+        // Archived and removed repos are not watched for releases anymore
+        let watched = listing
+            .values()
+            .filter(|repo| !repo.archived)
+            .map(|repo| repo.full_name.clone())
+            .collect::<HashSet<RepoFullName>>();
+        self.repos
+            .retain(|full_name, _| watched.contains(full_name));
+        for full_name in watched {
+            // Keep already known repos so their release history is not lost
+            if self.repos.contains_key(&full_name) {
+                continue;
+            }
+            let repo =
+                match GithubRepo::new(full_name.as_str(), self.filter.clone(), self.format.clone())
+                {
+                    Ok(repo) => repo,
+                    Err(err) => {
+                        error!("cannot create GithubRepo: {:#?}", err);
+                        continue;
+                    }
+                };
             self.repos.insert(full_name, repo);
         }
+        self.listing = Some(listing);
+        messages
     }
 
-    async fn get_all_org_repos(&mut self) -> Result<Vec<GithubRepoLight>, Box<dyn Error>> {
-        debug!("fetching all repos for {} organization", self.name);
-        let mut page = 1;
-        let mut repo_listing: Vec<GithubRepoLight> = Vec::new();
-        let default_items_per_page = DEFAULT_ITEM_PER_PAGE.to_string();
-        loop {
-            trace!("fetching {} org repos: page {}", self.name, page);
-            let page_str = page.to_string();
-            let mut params = HashMap::new();
-            params.insert("type", "public");
-            params.insert("per_page", &default_items_per_page);
-            params.insert("page", page_str.as_str());
-            params.insert("sort", "full_name");
-            let url = format!("https://api.github.com/orgs/{}/repos", self.name);
-            let url = reqwest::Url::parse_with_params(&url, &params)?;
-            let agent = request_agent()?;
-            let resp = match agent
-                .get(url.as_str())
-                .header("Authorization", &format!("token {}", self.github_token))
-                .header("Accept", "application/vnd.github+json")
-                .form(&params)
-                .send()
-                .await
-            {
-                Ok(res) => res,
-                Err(e) => {
-                    error!("error: cannot listing all repo for {}: {}", self.name, e);
-                    break;
-                }
+    async fn listing_changes(
+        &mut self,
+        previous: &HashMap<RepoId, OrgRepo>,
+        listing: &HashMap<RepoId, OrgRepo>,
+    ) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for (id, repo) in listing.iter() {
+            let Some(old) = previous.get(id) else {
+                messages.push(format!(
+                    "🆕 new public repo in {}: [{}]({})",
+                    self.name, repo.full_name, repo.html_url
+                ));
+                continue;
             };
-
-            let body = match resp.text().await {
-                Ok(body) => body,
-                Err(e) => {
-                    error!("cannot get text: {:#?}", e);
-                    break;
-                }
+            if old.full_name != repo.full_name {
+                messages.push(format!(
+                    "✏️ {} has been renamed to [{}]({})",
+                    old.full_name, repo.full_name, repo.html_url
+                ));
+                self.move_release_history(&old.full_name, &repo.full_name);
+            }
+            match (old.archived, repo.archived) {
+                (false, true) => messages.push(format!(
+                    "📦 [{}]({}) has been archived",
+                    repo.full_name, repo.html_url
+                )),
+                (true, false) => messages.push(format!(
+                    "♻️ [{}]({}) has been unarchived",
+                    repo.full_name, repo.html_url
+                )),
+                _ => {}
             };
-
-            let mut fetched_repos: Vec<GithubRepoLight> = match serde_json::from_str(&body) {
-                Err(e) => {
-                    error!("cannot deserializing all repo for {}: {}", self.name, e);
-                    trace!("cannot deserializing all repo. Body: {}", body);
-                    break;
+        }
+        for (id, old) in previous.iter() {
+            if listing.contains_key(id) {
+                continue;
+            }
+            // Repos leaving the listing can still be found by id when transferred
+            let path = format!("/repositories/{}", id);
+            let message = match self.client.get::<OrgRepo>(&path).await {
+                Ok(repo) if !repo.is_in_org(&self.name) => format!(
+                    "🚚 {} has been transferred to [{}]({})",
+                    old.full_name, repo.full_name, repo.html_url
+                ),
+                Ok(_) => format!("🔒 {} is not public anymore", old.full_name),
+                Err(err) => {
+                    trace!("{}: {}", old.full_name, err);
+                    format!("🗑️ {} has been deleted", old.full_name)
                 }
-                Ok(body) => body,
             };
+            messages.push(message);
+        }
+        messages
+    }
 
-            let size = fetched_repos.len();
-            trace!(
-                "{} org: fetched {} repos in this page: {:#?}",
-                self.name,
-                size,
-                fetched_repos
-                    .iter()
-                    .map(|r| &r.full_name)
-                    .collect::<Vec::<&String>>()
-            );
+    fn move_release_history(&mut self, old_full_name: &str, full_name: &str) {
+        let Some(releases) = self
+            .repos
+            .remove(old_full_name)
+            .and_then(|repo| repo.known_releases())
+        else {
+            return;
+        };
+        match GithubRepo::new(full_name, self.filter.clone(), self.format.clone()) {
+            Ok(mut repo) => {
+                repo.set_known_releases(releases);
+                self.repos.insert(full_name.to_string(), repo);
+            }
+            Err(err) => error!("cannot create GithubRepo: {:#?}", err),
+        };
+    }
+}
 
-            repo_listing.append(&mut fetched_repos);
+// Full names of the public repos of an organisation, also used by github_ci
+// Archived repos are left out
+pub async fn get_all_org_repos(
+    client: &GithubClient,
+    org_name: &str,
+) -> Result<Vec<RepoFullName>, Box<dyn Error + Send + Sync>> {
+    let repo_listing = list_org_repos(client, org_name).await?;
+    Ok(repo_listing
+        .into_iter()
+        .filter(|repo| !repo.archived)
+        .map(|repo| repo.full_name)
+        .collect())
+}
 
-            if size < DEFAULT_ITEM_PER_PAGE {
-                break;
-            }
-            page += 1;
-        }
-        debug!(
-            "get all reprositories from {} organisation: {} found",
-            self.name,
-            repo_listing.len()
-        );
-        Ok(repo_listing)
+async fn list_org_repos(
+    client: &GithubClient,
+    org_name: &str,
+) -> Result<Vec<OrgRepo>, Box<dyn Error + Send + Sync>> {
+    debug!("fetching all repos for {} organization", org_name);
+    let path = format!(
+        "/orgs/{}/repos?type=public&sort=full_name&per_page={}",
+        org_name, DEFAULT_ITEM_PER_PAGE
+    );
+    let repo_listing = client.get_all::<OrgRepo>(&path).await?;
+    trace!(
+        "{} org: fetched repos: {:#?}",
+        org_name,
+        repo_listing
+            .iter()
+            .map(|r| &r.full_name)
+            .collect::<Vec<&String>>()
+    );
+    debug!(
+        "get all reprositories from {} organisation: {} found",
+        org_name,
+        repo_listing.len()
+    );
+    Ok(repo_listing)
+}
+
+fn graphql_batch_size() -> Result<usize, String> {
+    let Ok(batch_size) = config::var("GITHUB_GRAPHQL_BATCH_SIZE") else {
+        return Ok(DEFAULT_GRAPHQL_BATCH_SIZE);
+    };
+    match batch_size.parse::<usize>() {
+        Ok(batch_size) if batch_size <= MAX_GRAPHQL_BATCH_SIZE => Ok(batch_size),
+        _ => Err(format!(
+            "GITHUB_GRAPHQL_BATCH_SIZE: invalid batch size '{}', expected 0 to {}",
+            batch_size, MAX_GRAPHQL_BATCH_SIZE
+        )),
     }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct GithubRepoLight {
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct OrgRepo {
+    id: u64,
     full_name: String,
+    html_url: String,
+    #[serde(default)]
+    archived: bool,
+}
+
+impl OrgRepo {
+    fn is_in_org(&self, org_name: &str) -> bool {
+        self.full_name
+            .split('/')
+            .next()
+            .is_some_and(|owner| owner.eq_ignore_ascii_case(org_name))
+    }
 }
@@ -1,30 +1,75 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
-use crate::utils::request_agent;
+use crate::config;
+use crate::github::{self, GithubClient};
 use async_trait::async_trait;
 use chrono::prelude::{DateTime, Utc};
+use lazy_static::lazy_static;
 use log::{debug, error, info, trace, warn};
-use reqwest::Response;
+use regex::{Captures, Regex};
 use serde::Deserialize;
-use std::collections::HashMap;
-use std::env;
-use std::env::VarError;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
 use std::time::SystemTime;
 use tokio::sync::RwLock;
 use tokio::time::Duration;
 
-const DEFAULT_ITEM_PER_PAGE: usize = 60;
+const DEFAULT_ITEM_PER_PAGE: usize = 100;
+const DEFAULT_MAX_AGE_DAYS: i64 = 10;
+const DEFAULT_RELEASE_TEMPLATE: &str = "🚀 [{{repo}} {{name}}]({{url}}) released by {{author}} on {{date}} ({{assets}} asset(s)) {{compare}}\n{{excerpt}}";
+const DEFAULT_EXCERPT_LINES: usize = 5;
+// Newer releases are announced at the next run, older ones are already known
+const GRAPHQL_RELEASES_PER_REPO: usize = 20;
 
 pub fn params() -> Vec<ModuleParam> {
-    vec![
-        ModuleParam::new("GITHUB_TOKEN", "Github token to make api calls", true),
+    [
+        github::params(),
+        vec![
         ModuleParam::new(
             "GITHUB_REPOS_0_FULLNAME",
             "Specific github repo to watch. e.g. kubernetes/kubernetes. Can be multiple (0..)",
             false,
         ),
+        ModuleParam::new(
+            "GITHUB_RELEASE_TEMPLATE",
+            "Release announcement template, placeholders: {{repo}} {{name}} {{tag}} {{url}} {{author}} {{date}} {{assets}} {{compare}} {{compare_url}} {{excerpt}}",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_RELEASE_EXCERPT_LINES",
+            "Maximal number of release notes lines in announcements, 0 to disable (default: 5)",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_REPOS_0_PRERELEASES",
+            "Announce prereleases of this repo (default: true)",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_REPOS_0_TAG_INCLUDE",
+            "Only announce releases whose tag matches this regex (e.g. ^v)",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_REPOS_0_TAG_EXCLUDE",
+            "Don't announce releases whose tag matches this regex (e.g. ^nightly-)",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_REPOS_0_SEMVER",
+            "Only announce 'major' (X.0.0) or 'minor' (X.Y.0) releases (default: all)",
+            false,
+        ),
+        ModuleParam::new(
+            "GITHUB_REPOS_0_MAX_AGE_DAYS",
+            "Don't announce releases published more than this number of days ago (default: 10)",
+            false,
+        ),
+    ],
     ]
+    .concat()
 }
 
 #[async_trait]
@@ -33,18 +78,20 @@ impl Module for GithubRepos {
         "github_repos"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        params()
-    }
-
     async fn module_offering(&self, _modules: &[ModuleData]) {}
 
     async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        let mut all_messages = Vec::new();
         let mut repos = self.repos.write().await;
         for (_repo_full_name, repo) in repos.iter_mut() {
-            repo.run().await;
+            if let Some(mut messages) = repo.run().await {
+                all_messages.append(&mut messages);
+            }
         }
-        None
+        if all_messages.is_empty() {
+            return None;
+        }
+        Some(all_messages)
     }
 
     fn variation_durations(&self) -> Vec<Duration> {
@@ -52,11 +99,36 @@ impl Module for GithubRepos {
     }
 
     fn capabilities(&self) -> ModuleCapabilities {
-        ModuleCapabilities::default()
+        ModuleCapabilities {
+            triggers: Some(vec!["/releases".to_string()]),
+            ..ModuleCapabilities::default()
+        }
     }
 
-    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
-        None
+    async fn trigger(&self, message: &str) -> Option<Vec<MessageResponse>> {
+        trace!("responding to /releases");
+        let repos = self.repos.read().await;
+        let requested = message
+            .split("/releases")
+            .nth(1)
+            .and_then(|args| args.split_whitespace().next());
+        let Some(requested) = requested else {
+            let mut full_names = repos.keys().cloned().collect::<Vec<RepoFullName>>();
+            full_names.sort();
+            let response = format!(
+                "usage: /releases <owner/repo>, watched repos: {}",
+                full_names.join(", ")
+            );
+            return Some(vec![response]);
+        };
+        let repo = repos
+            .iter()
+            .find(|(full_name, _)| full_name.eq_ignore_ascii_case(requested));
+        let response = match repo {
+            Some((_, repo)) => repo.latest_releases_message(),
+            None => format!("{} is not watched", requested),
+        };
+        Some(vec![response])
     }
 
     async fn send_message(&self, _messages: &[Message]) {}
@@ -66,6 +138,32 @@ impl Module for GithubRepos {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let repos = self.repos.read().await;
+        let known_releases = repos
+            .iter()
+            .filter_map(|(full_name, repo)| Some((full_name.clone(), repo.known_releases()?)))
+            .collect::<HashMap<RepoFullName, HashSet<ReleaseId>>>();
+        serde_json::to_value(known_releases).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let mut known_releases: HashMap<RepoFullName, HashSet<ReleaseId>> =
+            match serde_json::from_value(state) {
+                Ok(known_releases) => known_releases,
+                Err(err) => {
+                    error!("cannot load github_repos state: {}", err);
+                    return;
+                }
+            };
+        let mut repos = self.repos.write().await;
+        for (full_name, repo) in repos.iter_mut() {
+            if let Some(releases) = known_releases.remove(full_name) {
+                repo.set_known_releases(releases);
+            }
+        }
+    }
 }
 
 type RepoFullName = String;
@@ -74,18 +172,14 @@ pub struct GithubRepos {
 }
 
 impl GithubRepos {
-    pub fn new() -> Result<Self, VarError> {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
         let mut repos = HashMap::new();
-        for i in 0..100 {
-            let var_fullname = env::var(format!("GITHUB_REPOS_{}_FULLNAME", i));
-            match var_fullname {
-                Ok(fullname) => {
-                    info!("github repo configured: {}", fullname);
-                    let new_repo = GithubRepo::new(fullname.as_str())?;
-                    repos.insert(fullname, new_repo);
-                }
-                _ => break,
-            }
+        let format = ReleaseFormat::from_config()?;
+        for (i, fullname) in configured_repos().into_iter().enumerate() {
+            info!("github repo configured: {}", fullname);
+            let filter = ReleaseFilter::from_config(&format!("GITHUB_REPOS_{}", i))?;
+            let new_repo = GithubRepo::new(fullname.as_str(), filter, format.clone())?;
+            repos.insert(fullname, new_repo);
         }
         if repos.is_empty() {
             warn!("github_repos module enabled bot not configuration provided");
@@ -96,24 +190,48 @@ impl GithubRepos {
     }
 }
 
-type ReleaseId = String;
+pub type ReleaseId = String;
+
+// Also used by github_orgs to leave these repos to github_repos
+pub fn configured_repos() -> Vec<RepoFullName> {
+    let mut full_names = Vec::new();
+    for i in 0..100 {
+        match config::var(format!("GITHUB_REPOS_{}_FULLNAME", i)) {
+            Ok(full_name) => full_names.push(full_name),
+            _ => break,
+        }
+    }
+    full_names
+}
+
+// Number of releases listed by /releases
+const LATEST_RELEASES_CNT: usize = 5;
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone)]
 pub struct GithubRepo {
     full_name: String,
     details: Option<GithubRepoLight>,
-    releases: Option<HashMap<ReleaseId, Release>>,
-    github_token: String,
+    releases: Option<HashSet<ReleaseId>>,
+    latest_releases: Vec<Release>,
+    filter: ReleaseFilter,
+    format: ReleaseFormat,
+    client: GithubClient,
 }
 
 impl GithubRepo {
-    pub fn new(full_name: &str) -> Result<Self, VarError> {
-        let full_name = full_name.into();
-        let github_token = env::var("GITHUB_TOKEN")?;
+    pub fn new(
+        full_name: &str,
+        filter: ReleaseFilter,
+        format: ReleaseFormat,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         Ok(GithubRepo {
-            full_name,
-            github_token,
-            ..Default::default()
+            full_name: full_name.into(),
+            filter,
+            format,
+            client: GithubClient::new()?,
+            details: None,
+            releases: None,
+            latest_releases: Vec::new(),
         })
     }
 
@@ -140,6 +258,31 @@ impl GithubRepo {
             error!("no release found for {}", self.full_name);
             return None;
         };
+        self.handle_releases(current_releases, true)
+    }
+
+    // Same as run with details and releases already fetched by batch_releases
+    pub fn run_batched(&mut self, batched: BatchedRepo) -> Option<Vec<Message>> {
+        debug!("github_repo: checking for batched repo {}", self.full_name);
+        self.details = Some(batched.details);
+        if self.is_maintained() != Some(true) {
+            trace!(
+                "repo {} is not maintained, not checking releases",
+                self.full_name
+            );
+            return None;
+        }
+        self.handle_releases(batched.releases, false)
+    }
+
+    // A complete listing holds all releases of the repo, known releases missing from it are
+    // forgotten
+    fn handle_releases(
+        &mut self,
+        current_releases: Vec<Release>,
+        complete: bool,
+    ) -> Option<Vec<Message>> {
+        self.update_latest_releases(&current_releases);
 
         if self.releases.is_none() {
             trace!(
@@ -147,27 +290,29 @@ impl GithubRepo {
                 self.full_name,
                 current_releases.len()
             );
-            let initial_release_map =
-                current_releases
-                    .into_iter()
-                    .fold(HashMap::new(), |mut map, release| {
-                        map.insert(release.id(), release);
-                        map
-                    });
-            self.releases = Some(initial_release_map);
+            let mut initial_releases = HashSet::new();
+            self.filter
+                .new_releases(&mut initial_releases, &current_releases);
+            self.releases = Some(initial_releases);
             return None;
         }
 
         let mut past_releases = self.releases.take()?;
         let mut all_messages = Vec::new();
-        for release in current_releases {
-            if past_releases
-                .insert(release.id(), release.clone())
-                .is_none()
-                && !release.is_too_old()
-            {
-                all_messages.push(release.notification_message(&self.full_name));
-            }
+        for release in self
+            .filter
+            .new_releases(&mut past_releases, &current_releases)
+        {
+            let previous = release.previous(&current_releases);
+            let message = self.format.render(&self.full_name, release, previous);
+            all_messages.push(message);
+        }
+        if complete {
+            let current_ids = current_releases
+                .iter()
+                .map(Release::id)
+                .collect::<HashSet<ReleaseId>>();
+            past_releases.retain(|id| current_ids.contains(id));
         }
         self.releases = Some(past_releases);
         if all_messages.is_empty() {
@@ -176,108 +321,69 @@ impl GithubRepo {
         Some(all_messages)
     }
 
+    pub fn known_releases(&self) -> Option<HashSet<ReleaseId>> {
+        self.releases.clone()
+    }
+
+    pub fn set_known_releases(&mut self, releases: HashSet<ReleaseId>) {
+        self.releases = Some(releases);
+    }
+
+    fn update_latest_releases(&mut self, releases: &[Release]) {
+        let mut latest_releases = releases
+            .iter()
+            .filter(|release| !release.draft)
+            .cloned()
+            .collect::<Vec<Release>>();
+        // RFC 3339 dates in UTC can be compared as strings
+        latest_releases.sort_by(|a, b| b.published_at.cmp(&a.published_at));
+        latest_releases.truncate(LATEST_RELEASES_CNT);
+        self.latest_releases = latest_releases;
+    }
+
+    pub fn latest_releases_message(&self) -> String {
+        if self.latest_releases.is_empty() {
+            return format!("no known release for {} yet", self.full_name);
+        }
+        let mut message = format!("latest releases of {}:\n", self.full_name);
+        for release in self.latest_releases.iter() {
+            let published_at = release.published_at.as_deref().unwrap_or("unpublished");
+            message.push_str(&format!(
+                "- [{}]({}) ({})\n",
+                release.title(),
+                release.html_url,
+                published_at
+            ));
+        }
+        message
+    }
+
     pub fn is_maintained(&self) -> Option<bool> {
         let details = self.details.as_ref()?;
         Some(!details.fork && !details.archived)
     }
 
     async fn get_repo_details(&mut self) {
-        let Ok(agent) = request_agent() else {
-            error!("cannot get request agent");
-            return;
-        };
-        let url = format!("https://api.github.com/repos/{}", self.full_name);
-        let resp = match agent
-            .get(&url)
-            .header("Authorization", &format!("token {}", self.github_token))
-            .header("Accept", "application/vnd.github+json")
-            .send()
-            .await
-        {
-            Ok(res) => res,
-            Err(err) => {
-                error!("cannot read repo {}: {:#?}", self.full_name, err);
-                return;
-            }
-        };
-
-        let body = match resp.text().await {
-            Ok(body) => body,
-            Err(e) => {
-                error!("cannot get text: {:#?}", e);
-                return;
-            }
+        let path = format!("/repos/{}", self.full_name);
+        match self.client.get::<GithubRepoLight>(&path).await {
+            Ok(details) => self.details = Some(details),
+            Err(err) => error!("cannot read repo {}: {}", self.full_name, err),
         };
-
-        let repo_light: GithubRepoLight = match serde_json::from_str(&body) {
-            Err(err) => {
-                error!("cannot deserializing repo {}: {:#?}", self.full_name, err);
-                return;
-            }
-            Ok(body) => body,
-        };
-        self.details = Some(repo_light);
     }
 
     async fn get_releases(&self) -> Option<Vec<Release>> {
         debug!("getting all releases for github repo {}", self.full_name);
-        let mut page = 1;
-        let mut release_list: Vec<Release> = Vec::new();
-        let default_items_per_page = DEFAULT_ITEM_PER_PAGE.to_string();
-
-        loop {
-            let Ok(agent) = request_agent() else {
+        let path = format!(
+            "/repos/{}/releases?per_page={}",
+            self.full_name, DEFAULT_ITEM_PER_PAGE
+        );
+        let release_list = match self.client.get_all::<Release>(&path).await {
+            Ok(release_list) => release_list,
+            Err(err) => {
+                error!("cannot get releases of {}: {}", self.full_name, err);
                 return None;
-            };
-            let page_str = page.to_string();
-            let mut params = HashMap::new();
-            params.insert("type", "public");
-            params.insert("per_page", &default_items_per_page);
-            params.insert("page", page_str.as_str());
-            let url = format!("https://api.github.com/repos/{}/releases", self.full_name);
-            let Ok(url) = reqwest::Url::parse_with_params(&url, &params) else {
-                break;
-            };
-            let resp: Response = match agent
-                .get(url.as_str())
-                .header("Authorization", &format!("token {}", self.github_token))
-                .header("Accept", "application/vnd.github+json")
-                .form(&params)
-                .send()
-                .await
-            {
-                Ok(res) => res,
-                Err(e) => {
-                    error!("cannot get releases: {:#?}:", e);
-                    return None;
-                }
-            };
-
-            let body = match resp.text().await {
-                Ok(body) => body,
-                Err(e) => {
-                    error!("cannot get text: {:#?}", e);
-                    return None;
-                }
-            };
-
-            let mut releases: Vec<Release> = match serde_json::from_str(&body) {
-                Err(e) => {
-                    error!("cannot deserializing releases: {:#?}", e);
-                    return None;
-                }
-                Ok(body) => body,
-            };
-            let size = releases.len();
-
-            release_list.append(&mut releases);
-
-            if size < DEFAULT_ITEM_PER_PAGE {
-                break;
             }
-
-            page += 1;
-        }
+        };
         trace!("release list for {}: {:#?}", self.full_name, release_list);
         Some(release_list)
     }
@@ -287,22 +393,84 @@ impl GithubRepo {
 struct Release {
     html_url: String,
     tag_name: String,
-    name: String,
+    // Name and body are null when not filled on GitHub
+    name: Option<String>,
     prerelease: bool,
     draft: bool,
-    body: String,
+    body: Option<String>,
     published_at: Option<String>,
+    author: Option<ReleaseAuthor>,
+    #[serde(default)]
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash)]
+struct ReleaseAuthor {
+    login: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash)]
+struct ReleaseAsset {
+    name: String,
 }
 
 impl Release {
-    fn notification_message(&self, project_name: &String) -> String {
+    fn title(&self) -> &str {
+        match self.name.as_deref() {
+            Some(name) if !name.trim().is_empty() => name,
+            _ => &self.tag_name,
+        }
+    }
+
+    // Release published just before this one, prereleases are only compared with prereleases
+    fn previous<'a>(&self, releases: &'a [Release]) -> Option<&'a Release> {
+        let published_at = self.published_at.as_ref()?;
+        releases
+            .iter()
+            .filter(|release| !release.draft && (self.prerelease || !release.prerelease))
+            .filter(|release| {
+                release
+                    .published_at
+                    .as_ref()
+                    .is_some_and(|date| date < published_at)
+            })
+            .max_by(|a, b| a.published_at.cmp(&b.published_at))
+    }
+
+    fn compare_url(&self, previous: &Release) -> String {
+        // html_url looks like https://github.com/<owner>/<repo>/releases/tag/<tag>
+        let repo_url = self.html_url.split("/releases/").next().unwrap_or_default();
         format!(
-            "👋 Release de [{} {}]({})",
-            project_name, self.name, self.html_url
+            "{}/compare/{}...{}",
+            repo_url, previous.tag_name, self.tag_name
         )
     }
 
-    fn is_too_old(&self) -> bool {
+    // Breaking changes section of the release notes if any, first lines otherwise
+    fn excerpt(&self, max_lines: usize) -> String {
+        if max_lines == 0 {
+            return String::new();
+        }
+        let body = self.body.as_deref().unwrap_or_default();
+        let lines = breaking_changes_section(body).unwrap_or(body.lines().collect());
+        let lines = lines
+            .into_iter()
+            .map(str::trim_end)
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with("<!--"))
+            .collect::<Vec<&str>>();
+        let mut excerpt = lines
+            .iter()
+            .take(max_lines)
+            .cloned()
+            .collect::<Vec<&str>>()
+            .join("\n");
+        if lines.len() > max_lines {
+            excerpt.push_str("\n…");
+        }
+        excerpt
+    }
+
+    fn is_too_old(&self, max_age_days: i64) -> bool {
         let Some(published_at) = self.published_at.clone() else {
             return false;
         };
@@ -313,19 +481,378 @@ impl Release {
         let now_date = SystemTime::now();
         let now_date: DateTime<Utc> = now_date.into();
         let diff = now_date - published_date;
-        if diff.num_days() < 10 {
+        if diff.num_days() < max_age_days {
             return false;
         }
         true
     }
 
+    // Major, minor and patch numbers of tags like v1.2.3, None for other tags (e.g. v1.2.3-rc.1)
+    fn semver(&self) -> Option<(u64, u64, u64)> {
+        lazy_static! {
+            static ref SEMVER: Regex = Regex::new(r"^[^\d]*(\d+)\.(\d+)(?:\.(\d+))?$").unwrap();
+        }
+        let captures = SEMVER.captures(&self.tag_name)?;
+        let major = captures.get(1)?.as_str().parse().ok()?;
+        let minor = captures.get(2)?.as_str().parse().ok()?;
+        let patch = match captures.get(3) {
+            Some(patch) => patch.as_str().parse().ok()?,
+            None => 0,
+        };
+        Some((major, minor, patch))
+    }
+
     fn id(&self) -> String {
         self.tag_name.clone()
     }
 }
 
+fn breaking_changes_section(body: &str) -> Option<Vec<&str>> {
+    let heading_level = |line: &str| {
+        let level = line.chars().take_while(|c| *c == '#').count();
+        match level > 0 && line[level..].starts_with(' ') {
+            true => Some(level),
+            false => None,
+        }
+    };
+    let lines = body.lines().collect::<Vec<&str>>();
+    let (start, level) = lines.iter().enumerate().find_map(|(i, line)| {
+        let level = heading_level(line.trim())?;
+        match line.to_lowercase().contains("breaking change") {
+            true => Some((i, level)),
+            false => None,
+        }
+    })?;
+    let section = lines[start..]
+        .iter()
+        .enumerate()
+        .take_while(|(i, line)| {
+            *i == 0 || heading_level(line.trim()).is_none_or(|other| other > level)
+        })
+        .map(|(_, line)| *line)
+        .collect();
+    Some(section)
+}
+
+#[derive(Clone, Debug)]
+pub struct ReleaseFormat {
+    template: String,
+    excerpt_lines: usize,
+}
+
+impl Default for ReleaseFormat {
+    fn default() -> Self {
+        ReleaseFormat {
+            template: DEFAULT_RELEASE_TEMPLATE.to_string(),
+            excerpt_lines: DEFAULT_EXCERPT_LINES,
+        }
+    }
+}
+
+impl ReleaseFormat {
+    pub fn from_config() -> Result<ReleaseFormat, String> {
+        let mut format = ReleaseFormat::default();
+        if let Ok(template) = config::var("GITHUB_RELEASE_TEMPLATE") {
+            // Environment variables cannot easily hold new lines
+            format.template = template.replace("\\n", "\n");
+        }
+        if let Ok(excerpt_lines) = config::var("GITHUB_RELEASE_EXCERPT_LINES") {
+            format.excerpt_lines = excerpt_lines.parse().map_err(|err| {
+                format!("GITHUB_RELEASE_EXCERPT_LINES: '{}': {}", excerpt_lines, err)
+            })?;
+        }
+        Ok(format)
+    }
+
+    fn render(&self, repo: &str, release: &Release, previous: Option<&Release>) -> String {
+        lazy_static! {
+            static ref PLACEHOLDER: Regex = Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap();
+        }
+        let date = release
+            .published_at
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or("unpublished".to_string());
+        let compare_url = previous
+            .map(|previous| release.compare_url(previous))
+            .unwrap_or_default();
+        let message =
+            PLACEHOLDER.replace_all(&self.template, |captures: &Captures| match &captures[1] {
+                "repo" => repo.to_string(),
+                "name" => release.title().to_string(),
+                "tag" => release.tag_name.clone(),
+                "url" => release.html_url.clone(),
+                "author" => release
+                    .author
+                    .as_ref()
+                    .map(|author| author.login.clone())
+                    .unwrap_or("unknown".to_string()),
+                "date" => date.clone(),
+                "assets" => release.assets.len().to_string(),
+                "compare" => match previous {
+                    Some(previous) => format!(
+                        "[{}...{}]({})",
+                        previous.tag_name, release.tag_name, compare_url
+                    ),
+                    None => String::new(),
+                },
+                "compare_url" => compare_url.clone(),
+                "excerpt" => release.excerpt(self.excerpt_lines),
+                _ => captures[0].to_string(),
+            });
+        // Empty placeholders may leave trailing spaces and empty lines
+        message
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<&str>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+enum SemverLevel {
+    #[default]
+    All,
+    Minor,
+    Major,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReleaseFilter {
+    prereleases: bool,
+    tag_include: Option<Regex>,
+    tag_exclude: Option<Regex>,
+    semver: SemverLevel,
+    max_age_days: i64,
+}
+
+impl Default for ReleaseFilter {
+    fn default() -> Self {
+        ReleaseFilter {
+            prereleases: true,
+            tag_include: None,
+            tag_exclude: None,
+            semver: SemverLevel::All,
+            max_age_days: DEFAULT_MAX_AGE_DAYS,
+        }
+    }
+}
+
+impl ReleaseFilter {
+    // Read rules from <prefix>_PRERELEASES, <prefix>_TAG_INCLUDE, ...
+    pub fn from_config(prefix: &str) -> Result<ReleaseFilter, String> {
+        let var = |name: &str| config::var(format!("{}_{}", prefix, name)).ok();
+        let regex = |name: &str| match var(name) {
+            Some(pattern) => Regex::new(&pattern)
+                .map(Some)
+                .map_err(|err| format!("{}_{}: invalid regex: {}", prefix, name, err)),
+            None => Ok(None),
+        };
+        let mut filter = ReleaseFilter {
+            tag_include: regex("TAG_INCLUDE")?,
+            tag_exclude: regex("TAG_EXCLUDE")?,
+            ..ReleaseFilter::default()
+        };
+        if let Some(prereleases) = var("PRERELEASES") {
+            filter.prereleases = matches!(prereleases.as_str(), "1" | "true");
+        }
+        filter.semver = match var("SEMVER").as_deref() {
+            None | Some("all") => SemverLevel::All,
+            Some("minor") => SemverLevel::Minor,
+            Some("major") => SemverLevel::Major,
+            Some(other) => return Err(format!("{}_SEMVER: unknown level '{}'", prefix, other)),
+        };
+        if let Some(max_age_days) = var("MAX_AGE_DAYS") {
+            filter.max_age_days = match max_age_days.parse() {
+                Ok(max_age_days) if max_age_days >= 0 => max_age_days,
+                _ => {
+                    return Err(format!(
+                        "{}_MAX_AGE_DAYS: invalid number of days '{}'",
+                        prefix, max_age_days
+                    ))
+                }
+            };
+        }
+        Ok(filter)
+    }
+
+    // Accepted releases not known yet, they are added to the known ones. Rejected releases are
+    // not recorded so that publishing a draft or promoting a prerelease is announced later.
+    fn new_releases<'a>(
+        &self,
+        known: &mut HashSet<ReleaseId>,
+        releases: &'a [Release],
+    ) -> Vec<&'a Release> {
+        releases
+            .iter()
+            .filter(|release| self.accepts(release))
+            .filter(|release| known.insert(release.id()))
+            .collect()
+    }
+
+    fn accepts(&self, release: &Release) -> bool {
+        if release.draft || (release.prerelease && !self.prereleases) {
+            return false;
+        }
+        if let Some(tag_include) = self.tag_include.as_ref() {
+            if !tag_include.is_match(&release.tag_name) {
+                return false;
+            }
+        }
+        if let Some(tag_exclude) = self.tag_exclude.as_ref() {
+            if tag_exclude.is_match(&release.tag_name) {
+                return false;
+            }
+        }
+        let semver_accepted = match (&self.semver, release.semver()) {
+            (SemverLevel::All, _) => true,
+            (SemverLevel::Minor, Some((_, _, patch))) => patch == 0,
+            (SemverLevel::Major, Some((_, minor, patch))) => minor == 0 && patch == 0,
+            (_, None) => false,
+        };
+        if !semver_accepted {
+            trace!("release {} filtered by semver rule", release.tag_name);
+            return false;
+        }
+        !release.is_too_old(self.max_age_days)
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct GithubRepoLight {
     archived: bool,
     fork: bool,
 }
+
+// Details and latest releases of a repo fetched along other repos in a single GraphQL query
+pub struct BatchedRepo {
+    details: GithubRepoLight,
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRepo {
+    is_archived: bool,
+    is_fork: bool,
+    releases: GraphqlNodes<GraphqlRelease>,
+}
+
+#[derive(Deserialize)]
+struct GraphqlNodes<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRelease {
+    url: String,
+    tag_name: String,
+    name: Option<String>,
+    is_prerelease: bool,
+    is_draft: bool,
+    description: Option<String>,
+    published_at: Option<String>,
+    author: Option<ReleaseAuthor>,
+    release_assets: GraphqlNodes<ReleaseAsset>,
+}
+
+impl From<GraphqlRelease> for Release {
+    fn from(release: GraphqlRelease) -> Self {
+        Release {
+            html_url: release.url,
+            tag_name: release.tag_name,
+            name: release.name,
+            prerelease: release.is_prerelease,
+            draft: release.is_draft,
+            body: release.description,
+            published_at: release.published_at,
+            author: release.author,
+            assets: release.release_assets.nodes,
+        }
+    }
+}
+
+// Repos missing from the result could not be fetched (e.g. renamed or deleted)
+pub async fn batch_releases(
+    client: &GithubClient,
+    full_names: &[RepoFullName],
+) -> Result<HashMap<RepoFullName, BatchedRepo>, Box<dyn Error + Send + Sync>> {
+    let mut query = String::from("query {\n");
+    for (i, full_name) in full_names.iter().enumerate() {
+        let Some((owner, name)) = full_name.split_once('/') else {
+            continue;
+        };
+        // JSON strings are valid GraphQL strings
+        query.push_str(&format!(
+            "r{}: repository(owner: {}, name: {}) {{ ...repo }}\n",
+            i,
+            serde_json::to_string(owner)?,
+            serde_json::to_string(name)?
+        ));
+    }
+    query.push_str(&format!(
+        "}}\nfragment repo on Repository {{ isArchived isFork \
+         releases(first: {}, orderBy: {{field: CREATED_AT, direction: DESC}}) {{ nodes {{ \
+         url tagName name isPrerelease isDraft description publishedAt author {{ login }} \
+         releaseAssets(first: 100) {{ nodes {{ name }} }} }} }} }}",
+        GRAPHQL_RELEASES_PER_REPO
+    ));
+    let mut data: HashMap<String, Option<GraphqlRepo>> = client.graphql(&query).await?;
+    let mut batched = HashMap::new();
+    for (i, full_name) in full_names.iter().enumerate() {
+        let Some(Some(repo)) = data.remove(&format!("r{}", i)) else {
+            continue;
+        };
+        let repo = BatchedRepo {
+            details: GithubRepoLight {
+                archived: repo.is_archived,
+                fork: repo.is_fork,
+            },
+            releases: repo.releases.nodes.into_iter().map(Release::from).collect(),
+        };
+        batched.insert(full_name.clone(), repo);
+    }
+    Ok(batched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(tag_name: &str, draft: bool) -> Release {
+        serde_json::from_value(serde_json::json!({
+            "html_url": format!("https://github.com/owner/repo/releases/tag/{}", tag_name),
+            "tag_name": tag_name,
+            "name": null,
+            "prerelease": false,
+            "draft": draft,
+            "body": null,
+            "published_at": null,
+            "author": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn draft_then_published_release_is_announced() {
+        let filter = ReleaseFilter::default();
+        let mut known = HashSet::new();
+
+        let releases = vec![release("v1.0.0", false), release("v1.1.0", true)];
+        let new = filter.new_releases(&mut known, &releases);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].tag_name, "v1.0.0");
+
+        let releases = vec![release("v1.0.0", false), release("v1.1.0", true)];
+        assert!(filter.new_releases(&mut known, &releases).is_empty());
+
+        let releases = vec![release("v1.0.0", false), release("v1.1.0", false)];
+        let new = filter.new_releases(&mut known, &releases);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].tag_name, "v1.1.0");
+    }
+}
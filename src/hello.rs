@@ -1,5 +1,6 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
 use async_trait::async_trait;
 use rand::prelude::IteratorRandom;
@@ -9,16 +10,16 @@ use std::{
 };
 use tokio::time::Duration;
 
+pub fn params() -> Vec<ModuleParam> {
+    Vec::new()
+}
+
 #[async_trait]
 impl Module for Hello {
     fn name(&self) -> &'static str {
         "hello"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        Vec::new()
-    }
-
     async fn module_offering(&self, _modules: &[ModuleData]) {}
 
     async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
@@ -56,6 +57,12 @@ impl Module for Hello {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
 }
 
 pub struct Hello {
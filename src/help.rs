@@ -1,5 +1,6 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
 use async_trait::async_trait;
 use log::trace;
@@ -8,16 +9,16 @@ use std::env::VarError;
 use tokio::sync::RwLock;
 use tokio::time::Duration;
 
+pub fn params() -> Vec<ModuleParam> {
+    Vec::new()
+}
+
 #[async_trait]
 impl Module for Help {
     fn name(&self) -> &'static str {
         "help"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        Vec::new()
-    }
-
     async fn module_offering(&self, modules: &[ModuleData]) {
         let mut lock = self.commands.write().await;
         for module in modules {
@@ -65,6 +66,12 @@ impl Module for Help {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
 }
 
 pub struct Help {
@@ -0,0 +1,76 @@
+use crate::bot::ModuleParam;
+use crate::config;
+use axum::routing::MethodRouter;
+use axum::Router;
+use log::info;
+use std::error::Error;
+use std::sync::Mutex;
+use tokio::net::TcpListener;
+
+const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:8080";
+
+// Routes registered by modules, all served by a single listener
+static ROUTES: Mutex<Option<Routes>> = Mutex::new(None);
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![ModuleParam::new(
+        "HTTP_LISTEN_ADDRESS",
+        "Address of the HTTP server used by modules receiving requests (default: 0.0.0.0:8080)",
+        false,
+    )]
+}
+
+struct Routes {
+    router: Router,
+    paths: Vec<String>,
+}
+
+pub fn route(path: &str, method_router: MethodRouter) -> Result<(), String> {
+    if !path.starts_with('/') {
+        return Err(format!("http path '{}' must start with '/'", path));
+    }
+    let mut lock = ROUTES.lock().unwrap_or_else(|err| err.into_inner());
+    let routes = lock.get_or_insert_with(|| Routes {
+        router: Router::new(),
+        paths: Vec::new(),
+    });
+    if routes.paths.iter().any(|known| known == path) {
+        return Err(format!("http path '{}' is already used", path));
+    }
+    routes.router = std::mem::take(&mut routes.router).route(path, method_router);
+    routes.paths.push(path.to_string());
+    Ok(())
+}
+
+pub struct HttpServer {
+    listener: TcpListener,
+    routes: Routes,
+}
+
+// Binds the listener if any module registered a route, so address errors are reported at startup
+pub async fn bind() -> Result<Option<HttpServer>, Box<dyn Error + Send + Sync>> {
+    let routes = {
+        let mut lock = ROUTES.lock().unwrap_or_else(|err| err.into_inner());
+        lock.take()
+    };
+    let Some(routes) = routes else {
+        return Ok(None);
+    };
+    let address = config::var("HTTP_LISTEN_ADDRESS").unwrap_or(DEFAULT_LISTEN_ADDRESS.to_string());
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|err| format!("cannot listen on {}: {}", address, err))?;
+    info!(
+        "http server listening on {}: {}",
+        address,
+        routes.paths.join(", ")
+    );
+    Ok(Some(HttpServer { listener, routes }))
+}
+
+impl HttpServer {
+    pub async fn serve(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        axum::serve(self.listener, self.routes.router).await?;
+        Ok(())
+    }
+}
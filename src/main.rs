@@ -2,28 +2,45 @@
 
 use bot::Bot;
 use clap::{command, Arg, ArgAction};
-use log::info;
+use log::{error, info};
 use std::process::exit;
 
+mod alertmanager;
 mod bot;
+mod certificates;
+mod config;
+mod discord;
+mod dns;
 mod down_detectors;
 mod feeds;
+mod github;
+mod github_activity;
+mod github_advisories;
+mod github_ci;
 mod github_orgs;
 mod github_repos;
 mod hello;
 mod help;
+mod http_server;
+mod matrix;
+mod mattermost;
+mod metrics;
 mod outscale_api_versions;
 mod ping;
+mod rocketchat;
 mod roll;
+mod slack;
+mod state;
 mod triggers;
 mod utils;
 mod webex;
+mod webhook_in;
+mod webhook_out;
 mod webpages;
 
 #[tokio::main]
 pub async fn main() {
-    env_logger::init();
-    let bot = Bot::new();
+    metrics::ErrorCountingLogger::init();
 
     let matches = command!()
         .arg(
@@ -32,12 +49,34 @@ pub async fn main() {
                 .long("show-params")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .help("TOML configuration file, environment variables override its values"),
+        )
         .get_matches();
 
+    if let Some(path) = matches.get_one::<String>("config") {
+        if let Err(err) = config::load_file(path) {
+            error!("cannot load configuration file {}: {}", path, err);
+            exit(1);
+        }
+    }
+
+    let mut bot = Bot::new();
+
     if matches.get_flag("show-params") {
         eprintln!("{}", bot.help().await);
         exit(0);
     }
+    bot.bind_http_server().await;
+    if !bot.config_errors().is_empty() {
+        for err in bot.config_errors() {
+            error!("{}", err);
+        }
+        exit(1);
+    }
     info!("bot will now run");
     bot.run().await;
 }
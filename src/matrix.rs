@@ -0,0 +1,422 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::metrics;
+use crate::utils::{request_agent, MeasuredSend};
+use async_trait::async_trait;
+use log::{error, info, trace, warn};
+use pulldown_cmark::{html, Parser};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env::VarError;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+// Long polling timeout of /sync, in milliseconds
+const SYNC_TIMEOUT_MS: u64 = 30_000;
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "MATRIX_HOMESERVER_URL",
+            "Matrix homeserver URL (e.g. https://matrix.org)",
+            true,
+        ),
+        ModuleParam::new(
+            "MATRIX_ACCESS_TOKEN",
+            "Access token of the bot's Matrix account",
+            true,
+        ),
+        ModuleParam::new(
+            "MATRIX_ROOM_0_ID",
+            "Matrix room id where to speak (e.g. !abc:matrix.org), can be multiple (0..)",
+            false,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for Matrix {
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        let sync = self.agent.sync().await;
+        metrics::chat_backend_reachable(self.name(), sync.is_ok());
+        if let Err(err) = sync {
+            error!("matrix sync: {}", err);
+            // Avoid hammering the homeserver when it is unreachable
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+        None
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            send_message: true,
+            read_message: true,
+            resp_message: true,
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(1)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, messages: &[Message]) {
+        for message in messages {
+            for room_id in self.agent.room_ids.iter() {
+                self.agent.say(room_id, message, None).await;
+            }
+        }
+    }
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        let mut lock = self.agent.mentions.lock().await;
+        if lock.is_empty() {
+            return None;
+        }
+        Some(lock.drain(..).collect())
+    }
+
+    async fn resp_message(&self, parent: MessageCtx, message: Message) {
+        trace!(
+            "richard responding to matrix event {}: {}",
+            parent.id,
+            message
+        );
+        let Some(reply_to) = ReplyTo::parse(&parent.id) else {
+            error!(
+                "matrix: cannot respond to malformed message id {}",
+                parent.id
+            );
+            return;
+        };
+        self.agent
+            .say(&reply_to.room_id.clone(), &message, Some(reply_to))
+            .await;
+    }
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let lock = self.agent.since.lock().await;
+        let since = lock.as_ref()?;
+        serde_json::to_value(since).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        match serde_json::from_value::<String>(state) {
+            Ok(since) => {
+                let mut lock = self.agent.since.lock().await;
+                *lock = Some(since);
+            }
+            Err(err) => error!("cannot load matrix state: {}", err),
+        }
+    }
+}
+
+pub struct Matrix {
+    agent: MatrixAgent,
+}
+
+impl Matrix {
+    pub fn new() -> Result<Self, VarError> {
+        Ok(Matrix {
+            agent: MatrixAgent::new()?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct MatrixAgent {
+    homeserver_url: String,
+    auth_header: String,
+    room_ids: Vec<String>,
+    user_id: Mutex<Option<String>>,
+    // Sync token of the last /sync call
+    since: Mutex<Option<String>>,
+    // Mentions received by /sync and not yet read by triggers
+    mentions: Mutex<Vec<MessageCtx>>,
+    transaction_cnt: AtomicU64,
+}
+
+impl MatrixAgent {
+    fn new() -> Result<MatrixAgent, VarError> {
+        let homeserver_url = config::var("MATRIX_HOMESERVER_URL")?;
+        let access_token = config::var("MATRIX_ACCESS_TOKEN")?;
+        let mut room_ids = Vec::new();
+        for i in 0..100 {
+            match config::var(format!("MATRIX_ROOM_{}_ID", i)) {
+                Ok(room_id) => {
+                    info!("matrix room configured: {}", room_id);
+                    room_ids.push(room_id);
+                }
+                _ => break,
+            }
+        }
+        if room_ids.is_empty() {
+            warn!("matrix module enabled but no room configured");
+        }
+        Ok(MatrixAgent {
+            homeserver_url: homeserver_url.trim_end_matches('/').to_string(),
+            auth_header: format!("Bearer {}", access_token),
+            room_ids,
+            ..MatrixAgent::default()
+        })
+    }
+
+    fn url(&self, path_segments: &[&str]) -> Result<Url, Box<dyn Error + Send + Sync>> {
+        let mut url = Url::parse(&self.homeserver_url)?;
+        url.path_segments_mut()
+            .map_err(|_| "homeserver URL cannot be a base")?
+            .extend(["_matrix", "client", "v3"])
+            .extend(path_segments);
+        Ok(url)
+    }
+
+    async fn user_id(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut lock = self.user_id.lock().await;
+        if let Some(user_id) = lock.as_ref() {
+            return Ok(user_id.clone());
+        }
+        let whoami: MatrixWhoami = request_agent()?
+            .get(self.url(&["account", "whoami"])?)
+            .header("Authorization", &self.auth_header)
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        trace!("matrix bot user id is {}", whoami.user_id);
+        *lock = Some(whoami.user_id.clone());
+        Ok(whoami.user_id)
+    }
+
+    async fn sync(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let user_id = self.user_id().await?;
+        let since = self.since.lock().await.clone();
+        let filter = json!({
+            "room": {
+                "rooms": self.room_ids,
+                "timeline": {"types": ["m.room.message"]},
+                "state": {"lazy_load_members": true},
+            },
+            "presence": {"types": []},
+            "account_data": {"types": []},
+        });
+        let mut url = self.url(&["sync"])?;
+        url.query_pairs_mut()
+            .append_pair("filter", &filter.to_string())
+            .append_pair("timeout", &SYNC_TIMEOUT_MS.to_string());
+        if let Some(since) = since.as_ref() {
+            url.query_pairs_mut().append_pair("since", since);
+        }
+        let response: MatrixSync = request_agent()?
+            .get(url)
+            .header("Authorization", &self.auth_header)
+            .timeout(Duration::from_millis(SYNC_TIMEOUT_MS + 30_000))
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // The initial sync only gives us a starting point, don't answer to old messages
+        if since.is_some() {
+            let mut mentions = Vec::new();
+            for (room_id, room) in response.rooms.join {
+                if !self.room_ids.contains(&room_id) {
+                    continue;
+                }
+                for event in room.timeline.events {
+                    if event.sender == user_id || !event.mentions(&user_id) {
+                        continue;
+                    }
+                    trace!("matrix: mentioned in {} by {}", room_id, event.sender);
+                    let thread_root = event.thread_root().unwrap_or(event.event_id.clone());
+                    let reply_to = ReplyTo {
+                        room_id: room_id.clone(),
+                        thread_root,
+                        event_id: event.event_id.clone(),
+                    };
+                    mentions.push(MessageCtx {
+                        content: event.body(),
+                        id: reply_to.to_id(),
+                    });
+                }
+            }
+            self.mentions.lock().await.append(&mut mentions);
+        }
+        *self.since.lock().await = Some(response.next_batch);
+        Ok(())
+    }
+
+    async fn say(&self, room_id: &str, message: &str, reply_to: Option<ReplyTo>) {
+        let mut formatted_body = String::new();
+        html::push_html(&mut formatted_body, Parser::new(message));
+        let mut content = json!({
+            "msgtype": "m.text",
+            "body": message,
+            "format": "org.matrix.custom.html",
+            "formatted_body": formatted_body,
+        });
+        if let Some(reply_to) = reply_to {
+            content["m.relates_to"] = json!({
+                "rel_type": "m.thread",
+                "event_id": reply_to.thread_root,
+                "is_falling_back": true,
+                "m.in_reply_to": {"event_id": reply_to.event_id},
+            });
+        }
+        let transaction_id = self.transaction_id();
+        let url = match self.url(&[
+            "rooms",
+            room_id,
+            "send",
+            "m.room.message",
+            transaction_id.as_str(),
+        ]) {
+            Ok(url) => url,
+            Err(err) => {
+                error!("matrix send: {}", err);
+                return;
+            }
+        };
+        let Ok(agent) = request_agent() else {
+            error!("cannot get request agent");
+            return;
+        };
+        match agent
+            .put(url)
+            .header("Authorization", &self.auth_header)
+            .json(&content)
+            .send_measured()
+            .await
+        {
+            Ok(resp) if !resp.status().is_success() => error!(
+                "matrix send: status: {}, content: {:#?}",
+                resp.status(),
+                resp.text().await
+            ),
+            Ok(resp) => trace!("matrix send: status: {}", resp.status()),
+            Err(err) => error!("matrix send: {}", err),
+        };
+    }
+
+    // Transaction ids must be unique for the access token, even across restarts
+    fn transaction_id(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let cnt = self.transaction_cnt.fetch_add(1, Ordering::Relaxed);
+        format!("richard-{}-{}", now, cnt)
+    }
+}
+
+// Where to answer to a message, serialized in MessageCtx::id
+struct ReplyTo {
+    room_id: String,
+    thread_root: String,
+    event_id: String,
+}
+
+impl ReplyTo {
+    fn to_id(&self) -> String {
+        format!("{} {} {}", self.room_id, self.thread_root, self.event_id)
+    }
+
+    fn parse(id: &str) -> Option<ReplyTo> {
+        let mut parts = id.split(' ');
+        Some(ReplyTo {
+            room_id: parts.next()?.to_string(),
+            thread_root: parts.next()?.to_string(),
+            event_id: parts.next()?.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MatrixWhoami {
+    user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MatrixSync {
+    next_batch: String,
+    #[serde(default)]
+    rooms: MatrixRooms,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct MatrixRooms {
+    #[serde(default)]
+    join: HashMap<String, MatrixJoinedRoom>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct MatrixJoinedRoom {
+    #[serde(default)]
+    timeline: MatrixTimeline,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct MatrixTimeline {
+    #[serde(default)]
+    events: Vec<MatrixEvent>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MatrixEvent {
+    event_id: String,
+    sender: String,
+    #[serde(default)]
+    content: Value,
+}
+
+impl MatrixEvent {
+    fn body(&self) -> String {
+        self.content["body"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn mentions(&self, user_id: &str) -> bool {
+        let explicit_mention = self.content["m.mentions"]["user_ids"]
+            .as_array()
+            .is_some_and(|ids| ids.iter().any(|id| id.as_str() == Some(user_id)));
+        // Older clients only put the user id or localpart in the body
+        let localpart = user_id
+            .trim_start_matches('@')
+            .split(':')
+            .next()
+            .unwrap_or_default();
+        let body = self.body();
+        explicit_mention
+            || body.contains(user_id)
+            || (!localpart.is_empty() && body.contains(localpart))
+    }
+
+    fn thread_root(&self) -> Option<String> {
+        let relates_to = &self.content["m.relates_to"];
+        if relates_to["rel_type"].as_str() != Some("m.thread") {
+            return None;
+        }
+        relates_to["event_id"].as_str().map(|id| id.to_string())
+    }
+}
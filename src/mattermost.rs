@@ -0,0 +1,281 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::metrics;
+use crate::utils::{request_agent, MeasuredSend};
+use async_trait::async_trait;
+use log::{error, trace};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env::VarError;
+use std::error::Error;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "MATTERMOST_URL",
+            "Mattermost server URL (e.g. https://mattermost.example.com)",
+            true,
+        ),
+        ModuleParam::new("MATTERMOST_TOKEN", "Mattermost bot access token. See how to create a [bot account](https://developers.mattermost.com/integrate/reference/bot-accounts/).", true),
+        ModuleParam::new(
+            "MATTERMOST_CHANNEL_ID",
+            "Mattermost channel id where to speak",
+            true,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for Mattermost {
+    fn name(&self) -> &'static str {
+        "mattermost"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        None
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            send_message: true,
+            read_message: true,
+            resp_message: true,
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(2)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, messages: &[Message]) {
+        for message in messages {
+            self.agent.say(message, None).await;
+        }
+    }
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        let posts = match self.agent.unread_mentions().await {
+            Ok(posts) => {
+                metrics::chat_backend_reachable(self.name(), true);
+                posts
+            }
+            Err(err) => {
+                metrics::chat_backend_reachable(self.name(), false);
+                error!("mattermost: cannot read posts: {}", err);
+                return None;
+            }
+        };
+        let unread_messages = posts
+            .into_iter()
+            .map(|post| MessageCtx {
+                content: post.message,
+                // Answers always go to the thread, even when mentioned inside a thread
+                id: match post.root_id.is_empty() {
+                    true => post.id,
+                    false => post.root_id,
+                },
+            })
+            .collect::<Vec<MessageCtx>>();
+        if unread_messages.is_empty() {
+            return None;
+        }
+        Some(unread_messages)
+    }
+
+    async fn resp_message(&self, parent: MessageCtx, message: Message) {
+        trace!(
+            "richard responding to mattermost post {}: {}",
+            parent.id,
+            message
+        );
+        self.agent.say(&message, Some(parent.id)).await;
+    }
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let lock = self.agent.last_post_date.lock().await;
+        let last_post_date = lock.as_ref()?;
+        serde_json::to_value(last_post_date).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        match serde_json::from_value::<i64>(state) {
+            Ok(date) => {
+                let mut lock = self.agent.last_post_date.lock().await;
+                *lock = Some(date);
+            }
+            Err(err) => error!("cannot load mattermost state: {}", err),
+        }
+    }
+}
+
+pub struct Mattermost {
+    agent: MattermostAgent,
+}
+
+impl Mattermost {
+    pub fn new() -> Result<Self, VarError> {
+        Ok(Mattermost {
+            agent: MattermostAgent::new()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct MattermostAgent {
+    url: String,
+    auth_header: String,
+    channel_id: String,
+    me: Mutex<Option<MattermostUser>>,
+    // Creation date of the last read post, in milliseconds since epoch
+    last_post_date: Mutex<Option<i64>>,
+}
+
+#[derive(Clone, Debug, Serialize, Default)]
+struct MattermostCreatePost {
+    channel_id: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root_id: Option<String>,
+}
+
+impl MattermostAgent {
+    fn new() -> Result<MattermostAgent, VarError> {
+        let url = config::var("MATTERMOST_URL")?;
+        let token = config::var("MATTERMOST_TOKEN")?;
+        let channel_id = config::var("MATTERMOST_CHANNEL_ID")?;
+        Ok(MattermostAgent {
+            url: url.trim_end_matches('/').to_string(),
+            auth_header: format!("Bearer {}", token),
+            channel_id,
+            me: Mutex::new(None),
+            last_post_date: Mutex::new(None),
+        })
+    }
+
+    fn get<T: AsRef<str>>(&self, path: T) -> Result<RequestBuilder, Box<dyn Error + Send + Sync>> {
+        Ok(request_agent()?
+            .get(format!("{}/api/v4{}", self.url, path.as_ref()))
+            .header("Authorization", &self.auth_header))
+    }
+
+    async fn say(&self, message: &str, root_id: Option<String>) {
+        let request = MattermostCreatePost {
+            channel_id: self.channel_id.clone(),
+            message: message.to_string(),
+            root_id,
+        };
+        let Ok(agent) = request_agent() else {
+            error!("cannot get request agent");
+            return;
+        };
+        match agent
+            .post(format!("{}/api/v4/posts", self.url))
+            .header("Authorization", &self.auth_header)
+            .json(&request)
+            .send_measured()
+            .await
+        {
+            Ok(resp) if !resp.status().is_success() => error!(
+                "mattermost post: status: {}, content: {:#?}",
+                resp.status(),
+                resp.text().await
+            ),
+            Ok(resp) => trace!("mattermost post: status: {}", resp.status()),
+            Err(err) => error!("mattermost post: {}", err),
+        };
+    }
+
+    async fn me(&self) -> Result<MattermostUser, Box<dyn Error + Send + Sync>> {
+        let mut lock = self.me.lock().await;
+        if let Some(me) = lock.as_ref() {
+            return Ok(me.clone());
+        }
+        let me: MattermostUser = self
+            .get("/users/me")?
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        trace!("mattermost bot user is {} ({})", me.username, me.id);
+        *lock = Some(me.clone());
+        Ok(me)
+    }
+
+    async fn unread_mentions(&self) -> Result<Vec<MattermostPost>, Box<dyn Error + Send + Sync>> {
+        let me = self.me().await?;
+        let mention = format!("@{}", me.username);
+        let mut lock = self.last_post_date.lock().await;
+        let path = match *lock {
+            Some(last) => format!("/channels/{}/posts?since={}", self.channel_id, last),
+            None => format!("/channels/{}/posts?per_page=1", self.channel_id),
+        };
+        let body = self
+            .get(path)?
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        trace!("{}", body);
+        let post_list: MattermostPostList = serde_json::from_str(body.as_str())?;
+
+        // 'since' also returns edited and deleted posts, only keep new ones
+        let mut posts = post_list
+            .posts
+            .into_values()
+            .filter(|post| lock.is_none_or(|last| post.create_at > last))
+            .collect::<Vec<MattermostPost>>();
+        posts.sort_by_key(|post| post.create_at);
+
+        let first_read = lock.is_none();
+        if let Some(post) = posts.last() {
+            *lock = Some(post.create_at);
+        } else if first_read {
+            *lock = Some(0);
+        }
+        // Don't answer to posts sent before the bot started for the first time
+        if first_read {
+            return Ok(Vec::new());
+        }
+        posts.retain(|post| post.user_id != me.id && post.message.contains(&mention));
+        Ok(posts)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MattermostUser {
+    id: String,
+    username: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MattermostPostList {
+    #[serde(default)]
+    posts: HashMap<String, MattermostPost>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MattermostPost {
+    id: String,
+    create_at: i64,
+    user_id: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    root_id: String,
+}
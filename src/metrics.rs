@@ -0,0 +1,352 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::http_server;
+use async_trait::async_trait;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use lazy_static::lazy_static;
+use log::{error, info, Level, LevelFilter, Log, Metadata, Record};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+lazy_static! {
+    static ref COLLECTORS: Collectors = Collectors::new();
+}
+
+// Last known reachability of each chat backend, None until it has been contacted
+static CHAT_BACKENDS: Mutex<BTreeMap<&'static str, Option<bool>>> = Mutex::new(BTreeMap::new());
+
+pub fn params() -> Vec<ModuleParam> {
+    Vec::new()
+}
+
+#[async_trait]
+impl Module for Metrics {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    async fn module_offering(&self, modules: &[ModuleData]) {
+        let mut lock = CHAT_BACKENDS.lock().unwrap_or_else(|err| err.into_inner());
+        for module in modules.iter() {
+            if module.capabilities.read_message {
+                lock.entry(module.name).or_insert(None);
+            }
+        }
+    }
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        None
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities::default()
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(3600)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, _messages: &[Message]) {}
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
+}
+
+pub struct Metrics;
+
+impl Metrics {
+    pub fn new() -> Result<Self, String> {
+        let routes = [
+            ("/metrics", get(metrics)),
+            ("/healthz", get(healthz)),
+            ("/readyz", get(readyz)),
+        ];
+        for (path, handler) in routes {
+            http_server::route(path, handler)?;
+        }
+        info!("metrics and health endpoints configured");
+        Ok(Metrics)
+    }
+}
+
+async fn metrics() -> impl IntoResponse {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(err) = encoder.encode(&COLLECTORS.registry.gather(), &mut buffer) {
+        error!("metrics: cannot encode: {}", err);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            Vec::new(),
+        );
+    }
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        buffer,
+    )
+}
+
+// Alive as long as one chat backend can be reached
+async fn healthz() -> (StatusCode, String) {
+    let backends = CHAT_BACKENDS.lock().unwrap_or_else(|err| err.into_inner());
+    let all_down = !backends.is_empty() && backends.values().all(|up| *up == Some(false));
+    match all_down {
+        true => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no chat backend reachable\n".to_string(),
+        ),
+        false => (StatusCode::OK, "ok\n".to_string()),
+    }
+}
+
+// Ready once all chat backends have been reached
+async fn readyz() -> (StatusCode, String) {
+    let backends = CHAT_BACKENDS.lock().unwrap_or_else(|err| err.into_inner());
+    let not_ready = backends
+        .iter()
+        .filter(|(_, up)| **up != Some(true))
+        .map(|(name, up)| match up {
+            Some(_) => format!("{}: unreachable", name),
+            None => format!("{}: not contacted yet", name),
+        })
+        .collect::<Vec<String>>();
+    match not_ready.is_empty() {
+        true => (StatusCode::OK, "ready\n".to_string()),
+        false => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{}\n", not_ready.join("\n")),
+        ),
+    }
+}
+
+struct Collectors {
+    registry: Registry,
+    run_duration: HistogramVec,
+    messages: IntCounterVec,
+    errors: IntCounterVec,
+    http_requests: IntCounterVec,
+    mailbox_depth: IntGauge,
+    probes: IntCounterVec,
+    probe_alive: IntGaugeVec,
+    probe_error_rate: GaugeVec,
+    probe_latency: HistogramVec,
+    probe_degraded: IntGaugeVec,
+    chat_backend_up: IntGaugeVec,
+}
+
+impl Collectors {
+    fn new() -> Collectors {
+        let collectors = Collectors {
+            registry: Registry::new_custom(Some("richard".to_string()), None)
+                .expect("cannot create metrics registry"),
+            run_duration: HistogramVec::new(
+                HistogramOpts::new("module_run_duration_seconds", "Duration of module runs"),
+                &["module", "variation"],
+            )
+            .expect("invalid metric"),
+            messages: IntCounterVec::new(
+                Opts::new("module_messages_total", "Messages emitted by modules"),
+                &["module"],
+            )
+            .expect("invalid metric"),
+            errors: IntCounterVec::new(
+                Opts::new("errors_total", "Errors logged, by module"),
+                &["module"],
+            )
+            .expect("invalid metric"),
+            http_requests: IntCounterVec::new(
+                Opts::new(
+                    "http_requests_total",
+                    "HTTP requests made by modules, by host and status code",
+                ),
+                &["host", "status"],
+            )
+            .expect("invalid metric"),
+            mailbox_depth: IntGauge::new(
+                "mailbox_depth",
+                "Messages waiting to be sent to chat backends",
+            )
+            .expect("invalid metric"),
+            probes: IntCounterVec::new(
+                Opts::new("probes_total", "Down detector probes, by result"),
+                &["name", "result"],
+            )
+            .expect("invalid metric"),
+            probe_alive: IntGaugeVec::new(
+                Opts::new("probe_alive", "Whether a down detector target is alive"),
+                &["name"],
+            )
+            .expect("invalid metric"),
+            probe_error_rate: GaugeVec::new(
+                Opts::new("probe_error_rate", "Error rate of a down detector target"),
+                &["name"],
+            )
+            .expect("invalid metric"),
+            probe_latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "probe_latency_seconds",
+                    "Latency of successful down detector probes",
+                ),
+                &["name"],
+            )
+            .expect("invalid metric"),
+            probe_degraded: IntGaugeVec::new(
+                Opts::new(
+                    "probe_degraded",
+                    "Whether a down detector target is above its latency SLO",
+                ),
+                &["name"],
+            )
+            .expect("invalid metric"),
+            chat_backend_up: IntGaugeVec::new(
+                Opts::new("chat_backend_up", "Whether a chat backend is reachable"),
+                &["module"],
+            )
+            .expect("invalid metric"),
+        };
+        let registry = &collectors.registry;
+        registry
+            .register(Box::new(collectors.run_duration.clone()))
+            .and(registry.register(Box::new(collectors.messages.clone())))
+            .and(registry.register(Box::new(collectors.errors.clone())))
+            .and(registry.register(Box::new(collectors.http_requests.clone())))
+            .and(registry.register(Box::new(collectors.mailbox_depth.clone())))
+            .and(registry.register(Box::new(collectors.probes.clone())))
+            .and(registry.register(Box::new(collectors.probe_alive.clone())))
+            .and(registry.register(Box::new(collectors.probe_error_rate.clone())))
+            .and(registry.register(Box::new(collectors.probe_latency.clone())))
+            .and(registry.register(Box::new(collectors.probe_degraded.clone())))
+            .and(registry.register(Box::new(collectors.chat_backend_up.clone())))
+            .expect("cannot register metrics");
+        collectors
+    }
+}
+
+pub fn module_run(module: &str, variation: usize, duration: Duration, message_cnt: usize) {
+    COLLECTORS
+        .run_duration
+        .with_label_values(&[module, &variation.to_string()])
+        .observe(duration.as_secs_f64());
+    COLLECTORS
+        .messages
+        .with_label_values(&[module])
+        .inc_by(message_cnt as u64);
+}
+
+pub fn http_request(host: &str, status: Option<u16>) {
+    let status = status
+        .map(|status| status.to_string())
+        .unwrap_or("error".to_string());
+    COLLECTORS
+        .http_requests
+        .with_label_values(&[host, &status])
+        .inc();
+}
+
+pub fn mailbox_depth(depth: usize) {
+    COLLECTORS.mailbox_depth.set(depth as i64);
+}
+
+pub fn probe(name: &str, success: bool, alive: bool, error_rate: f32) {
+    let result = match success {
+        true => "success",
+        false => "failure",
+    };
+    COLLECTORS.probes.with_label_values(&[name, result]).inc();
+    COLLECTORS
+        .probe_alive
+        .with_label_values(&[name])
+        .set(alive as i64);
+    COLLECTORS
+        .probe_error_rate
+        .with_label_values(&[name])
+        .set(error_rate as f64);
+}
+
+pub fn probe_latency(name: &str, latency: Duration) {
+    COLLECTORS
+        .probe_latency
+        .with_label_values(&[name])
+        .observe(latency.as_secs_f64());
+}
+
+pub fn probe_degraded(name: &str, degraded: bool) {
+    COLLECTORS
+        .probe_degraded
+        .with_label_values(&[name])
+        .set(degraded as i64);
+}
+
+pub fn chat_backend_reachable(module: &'static str, reachable: bool) {
+    COLLECTORS
+        .chat_backend_up
+        .with_label_values(&[module])
+        .set(reachable as i64);
+    let mut lock = CHAT_BACKENDS.lock().unwrap_or_else(|err| err.into_inner());
+    lock.insert(module, Some(reachable));
+}
+
+// Forwards records to env_logger while counting errors per module
+pub struct ErrorCountingLogger {
+    inner: env_logger::Logger,
+}
+
+impl ErrorCountingLogger {
+    pub fn init() {
+        let inner = env_logger::Builder::from_default_env().build();
+        // Errors are always counted, even when they are not printed
+        let max_level = inner.filter().max(LevelFilter::Error);
+        if log::set_boxed_logger(Box::new(ErrorCountingLogger { inner })).is_ok() {
+            log::set_max_level(max_level);
+        }
+    }
+}
+
+impl Log for ErrorCountingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() == Level::Error || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Error {
+            let target = record.target();
+            let module = target
+                .strip_prefix("richard::")
+                .unwrap_or(target)
+                .split("::")
+                .next()
+                .unwrap_or(target);
+            COLLECTORS.errors.with_label_values(&[module]).inc();
+        }
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
@@ -1,14 +1,16 @@
-use crate::utils::request_agent;
+use crate::config;
+use crate::utils::{request_agent, MeasuredSend};
 use log::{debug, error, info, trace, warn};
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::env::{self, VarError};
+use std::env::VarError;
 use std::error::Error;
 use tokio::sync::RwLock;
 use tokio::time::Duration;
 
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
 use async_trait::async_trait;
 
@@ -17,27 +19,27 @@ pub struct OutscaleApiVersions {
     endpoints: Vec<Endpoint>,
 }
 
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "OUTSCALE_API_VERSIONS_0_NAME",
+            "Outscale region name of the endpoint, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "OUTSCALE_API_VERSIONS_0_ENDPOINT",
+            "Outscale region endpoint, can be multiple (0..)",
+            false,
+        ),
+    ]
+}
+
 #[async_trait]
 impl Module for OutscaleApiVersions {
     fn name(&self) -> &'static str {
         "outscale_api_versions"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        vec![
-            ModuleParam::new(
-                "OUTSCALE_API_VERSIONS_0_NAME",
-                "Outscale region name of the endpoint, can be multiple (0..)",
-                false,
-            ),
-            ModuleParam::new(
-                "OUTSCALE_API_VERSIONS_0_ENDPOINT",
-                "Outscale region endpoint, can be multiple (0..)",
-                false,
-            ),
-        ]
-    }
-
     async fn module_offering(&self, _modules: &[ModuleData]) {}
 
     async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
@@ -76,14 +78,40 @@ impl Module for OutscaleApiVersions {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let mut all_versions = HashMap::new();
+        for endpoint in self.endpoints.iter() {
+            let lock = endpoint.versions.read().await;
+            all_versions.insert(endpoint.name.clone(), lock.clone());
+        }
+        serde_json::to_value(all_versions).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let mut all_versions: HashMap<String, HashMap<String, u8>> =
+            match serde_json::from_value(state) {
+                Ok(all_versions) => all_versions,
+                Err(err) => {
+                    error!("cannot load outscale_api_versions state: {}", err);
+                    return;
+                }
+            };
+        for endpoint in self.endpoints.iter() {
+            if let Some(versions) = all_versions.remove(&endpoint.name) {
+                let mut lock = endpoint.versions.write().await;
+                *lock = versions;
+            }
+        }
+    }
 }
 
 impl OutscaleApiVersions {
     pub fn new() -> Result<OutscaleApiVersions, VarError> {
         let mut endpoints = OutscaleApiVersions::default();
         for i in 0..100 {
-            let name = env::var(format!("OUTSCALE_API_VERSIONS_{}_NAME", i));
-            let endpoint = env::var(format!("OUTSCALE_API_VERSIONS_{}_ENDPOINT", i));
+            let name = config::var(format!("OUTSCALE_API_VERSIONS_{}_NAME", i));
+            let endpoint = config::var(format!("OUTSCALE_API_VERSIONS_{}_ENDPOINT", i));
             match (name, endpoint) {
                 (Ok(name), Ok(endpoint)) => {
                     info!("outscale api version on {} is configured", name);
@@ -162,7 +190,7 @@ impl Endpoint {
     async fn get_version(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
         let body = request_agent()?
             .post(&self.endpoint)
-            .send()
+            .send_measured()
             .await?
             .text()
             .await?;
@@ -1,21 +1,22 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
 use async_trait::async_trait;
 use log::trace;
 use std::env::VarError;
 use tokio::time::Duration;
 
+pub fn params() -> Vec<ModuleParam> {
+    Vec::new()
+}
+
 #[async_trait]
 impl Module for Ping {
     fn name(&self) -> &'static str {
         "ping"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        Vec::new()
-    }
-
     async fn module_offering(&self, _modules: &[ModuleData]) {}
 
     async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
@@ -45,6 +46,12 @@ impl Module for Ping {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
 }
 
 pub struct Ping {}
@@ -0,0 +1,300 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::metrics;
+use crate::utils::{request_agent, MeasuredSend};
+use async_trait::async_trait;
+use log::{error, trace};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use std::env::VarError;
+use std::error::Error;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "ROCKETCHAT_URL",
+            "Rocket.Chat server URL (e.g. https://rocketchat.example.com)",
+            true,
+        ),
+        ModuleParam::new(
+            "ROCKETCHAT_USER_ID",
+            "User id of the bot account",
+            true,
+        ),
+        ModuleParam::new("ROCKETCHAT_TOKEN", "Personal access token of the bot account. See how to create a [personal access token](https://docs.rocket.chat/docs/manage-personal-access-tokens).", true),
+        ModuleParam::new(
+            "ROCKETCHAT_ROOM_ID",
+            "Rocket.Chat channel id where to speak",
+            true,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for RocketChat {
+    fn name(&self) -> &'static str {
+        "rocketchat"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        None
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            send_message: true,
+            read_message: true,
+            resp_message: true,
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(2)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, messages: &[Message]) {
+        for message in messages {
+            self.agent.say(message, None).await;
+        }
+    }
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        let messages = match self.agent.unread_mentions().await {
+            Ok(messages) => {
+                metrics::chat_backend_reachable(self.name(), true);
+                messages
+            }
+            Err(err) => {
+                metrics::chat_backend_reachable(self.name(), false);
+                error!("rocketchat: cannot read messages: {}", err);
+                return None;
+            }
+        };
+        let unread_messages = messages
+            .into_iter()
+            .map(|message| MessageCtx {
+                content: message.msg,
+                // Answers always go to the thread, even when mentioned inside a thread
+                id: message.tmid.unwrap_or(message.id),
+            })
+            .collect::<Vec<MessageCtx>>();
+        if unread_messages.is_empty() {
+            return None;
+        }
+        Some(unread_messages)
+    }
+
+    async fn resp_message(&self, parent: MessageCtx, message: Message) {
+        trace!(
+            "richard responding to rocketchat message {}: {}",
+            parent.id,
+            message
+        );
+        self.agent.say(&message, Some(parent.id)).await;
+    }
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let lock = self.agent.last_message_date.lock().await;
+        let last_message_date = lock.as_ref()?;
+        serde_json::to_value(last_message_date).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        match serde_json::from_value::<String>(state) {
+            Ok(date) => {
+                let mut lock = self.agent.last_message_date.lock().await;
+                *lock = Some(date);
+            }
+            Err(err) => error!("cannot load rocketchat state: {}", err),
+        }
+    }
+}
+
+pub struct RocketChat {
+    agent: RocketChatAgent,
+}
+
+impl RocketChat {
+    pub fn new() -> Result<Self, VarError> {
+        Ok(RocketChat {
+            agent: RocketChatAgent::new()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct RocketChatAgent {
+    url: String,
+    user_id: String,
+    token: String,
+    room_id: String,
+    username: Mutex<Option<String>>,
+    last_message_date: Mutex<Option<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct RocketChatPostMessage {
+    room_id: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tmid: Option<String>,
+}
+
+impl RocketChatAgent {
+    fn new() -> Result<RocketChatAgent, VarError> {
+        let url = config::var("ROCKETCHAT_URL")?;
+        let user_id = config::var("ROCKETCHAT_USER_ID")?;
+        let token = config::var("ROCKETCHAT_TOKEN")?;
+        let room_id = config::var("ROCKETCHAT_ROOM_ID")?;
+        Ok(RocketChatAgent {
+            url: url.trim_end_matches('/').to_string(),
+            user_id,
+            token,
+            room_id,
+            username: Mutex::new(None),
+            last_message_date: Mutex::new(None),
+        })
+    }
+
+    fn authenticated(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder
+            .header("X-User-Id", &self.user_id)
+            .header("X-Auth-Token", &self.token)
+    }
+
+    async fn say(&self, message: &str, tmid: Option<String>) {
+        let request = RocketChatPostMessage {
+            room_id: self.room_id.clone(),
+            text: message.to_string(),
+            tmid,
+        };
+        let Ok(agent) = request_agent() else {
+            error!("cannot get request agent");
+            return;
+        };
+        let builder = agent
+            .post(format!("{}/api/v1/chat.postMessage", self.url))
+            .json(&request);
+        match self.authenticated(builder).send_measured().await {
+            Ok(resp) if !resp.status().is_success() => error!(
+                "rocketchat post: status: {}, content: {:#?}",
+                resp.status(),
+                resp.text().await
+            ),
+            Ok(resp) => trace!("rocketchat post: status: {}", resp.status()),
+            Err(err) => error!("rocketchat post: {}", err),
+        };
+    }
+
+    async fn username(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut lock = self.username.lock().await;
+        if let Some(username) = lock.as_ref() {
+            return Ok(username.clone());
+        }
+        let builder = request_agent()?.get(format!("{}/api/v1/me", self.url));
+        let me: RocketChatUser = self
+            .authenticated(builder)
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        trace!("rocketchat bot username is {}", me.username);
+        *lock = Some(me.username.clone());
+        Ok(me.username)
+    }
+
+    async fn unread_mentions(
+        &self,
+    ) -> Result<Vec<RocketChatMessage>, Box<dyn Error + Send + Sync>> {
+        let mention = format!("@{}", self.username().await?);
+        let mut lock = self.last_message_date.lock().await;
+        let mut query = vec![
+            ("roomId", self.room_id.clone()),
+            ("count", "100".to_string()),
+        ];
+        if let Some(ref last) = *lock {
+            query.push(("oldest", last.clone()));
+        }
+        let url = reqwest::Url::parse_with_params(
+            &format!("{}/api/v1/channels.history", self.url),
+            &query,
+        )?;
+        let builder = request_agent()?.get(url);
+        let body = self
+            .authenticated(builder)
+            .send_measured()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        trace!("{}", body);
+        let mut history: RocketChatHistory = serde_json::from_str(body.as_str())?;
+
+        // Sort messages by date and filter seen messages
+        history.messages.sort_by(|a, b| a.ts.cmp(&b.ts));
+        if let Some(ref last) = *lock {
+            history.messages.retain(|m| m.ts.as_str() > last.as_str());
+        }
+
+        let first_read = lock.is_none();
+        if let Some(m) = history.messages.last() {
+            *lock = Some(m.ts.clone());
+        } else if first_read {
+            *lock = Some(String::from("1970-01-01T00:00:00.000Z"));
+        }
+        // Don't answer to messages sent before the bot started for the first time
+        if first_read {
+            return Ok(Vec::new());
+        }
+        history.messages.retain(|m| {
+            m.u.id != self.user_id
+                && (m.msg.contains(&mention)
+                    || m.mentions.iter().any(|user| user.id == self.user_id))
+        });
+        Ok(history.messages)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct RocketChatUser {
+    username: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct RocketChatHistory {
+    messages: Vec<RocketChatMessage>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct RocketChatMessage {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(default)]
+    msg: String,
+    ts: String,
+    u: RocketChatMessageUser,
+    tmid: Option<String>,
+    #[serde(default)]
+    mentions: Vec<RocketChatMessageUser>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct RocketChatMessageUser {
+    #[serde(rename = "_id")]
+    id: String,
+}
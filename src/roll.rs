@@ -1,5 +1,6 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
 use async_trait::async_trait;
 use log::trace;
@@ -9,16 +10,16 @@ use tokio::time::Duration;
 
 pub struct Roll {}
 
+pub fn params() -> Vec<ModuleParam> {
+    Vec::new()
+}
+
 #[async_trait]
 impl Module for Roll {
     fn name(&self) -> &'static str {
         "roll"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        Vec::new()
-    }
-
     async fn module_offering(&self, _modules: &[ModuleData]) {}
 
     async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
@@ -48,6 +49,12 @@ impl Module for Roll {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
 }
 
 impl Roll {
@@ -0,0 +1,336 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::metrics;
+use crate::utils::{request_agent, MeasuredSend};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use log::{error, trace};
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use std::env::VarError;
+use std::error::Error;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+const DEFAULT_SLACK_API_URL: &str = "https://slack.com/api";
+const HISTORY_PAGE_SIZE: usize = 200;
+// Bound the catch up after a long downtime
+const HISTORY_MAX_PAGES: usize = 10;
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new("SLACK_TOKEN", "Slack bot token (xoxb-...) with chat:write and channels:history scopes. See how to create a [Slack app](https://api.slack.com/start/quickstart).", true),
+        ModuleParam::new("SLACK_CHANNEL_ID", "Slack channel id where to speak", true),
+        ModuleParam::new(
+            "SLACK_API_URL",
+            "Slack Web API base URL (default: https://slack.com/api)",
+            false,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for Slack {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        None
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            send_message: true,
+            read_message: true,
+            resp_message: true,
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(2)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, messages: &[Message]) {
+        for message in messages {
+            self.agent.say(message, None).await;
+        }
+    }
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        let messages = match self.agent.unread_mentions().await {
+            Ok(messages) => {
+                metrics::chat_backend_reachable(self.name(), true);
+                messages
+            }
+            Err(err) => {
+                metrics::chat_backend_reachable(self.name(), false);
+                error!("slack: cannot read messages: {}", err);
+                return None;
+            }
+        };
+        let unread_messages = messages
+            .into_iter()
+            .map(|message| MessageCtx {
+                content: message.text,
+                // conversations.history only lists thread replies also sent to the channel,
+                // answers go to their thread
+                id: message.thread_ts.unwrap_or(message.ts),
+            })
+            .collect::<Vec<MessageCtx>>();
+        if unread_messages.is_empty() {
+            return None;
+        }
+        Some(unread_messages)
+    }
+
+    async fn resp_message(&self, parent: MessageCtx, message: Message) {
+        trace!(
+            "richard responding to slack thread {}: {}",
+            parent.id,
+            message
+        );
+        self.agent.say(&message, Some(parent.id)).await;
+    }
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let lock = self.agent.last_seen_ts.lock().await;
+        let last_seen_ts = lock.as_ref()?;
+        serde_json::to_value(last_seen_ts).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        match serde_json::from_value::<String>(state) {
+            Ok(ts) => {
+                let mut lock = self.agent.last_seen_ts.lock().await;
+                *lock = Some(ts);
+            }
+            Err(err) => error!("cannot load slack state: {}", err),
+        }
+    }
+}
+
+pub struct Slack {
+    agent: SlackAgent,
+}
+
+impl Slack {
+    pub fn new() -> Result<Self, VarError> {
+        Ok(Slack {
+            agent: SlackAgent::new()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct SlackAgent {
+    api_url: String,
+    auth_header: String,
+    channel_id: String,
+    bot_user_id: Mutex<Option<String>>,
+    last_seen_ts: Mutex<Option<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Default)]
+struct SlackPostMessage {
+    channel: String,
+    text: String,
+    mrkdwn: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_ts: Option<String>,
+}
+
+impl SlackAgent {
+    fn new() -> Result<SlackAgent, VarError> {
+        let token = config::var("SLACK_TOKEN")?;
+        let channel_id = config::var("SLACK_CHANNEL_ID")?;
+        let api_url = config::var("SLACK_API_URL").unwrap_or(DEFAULT_SLACK_API_URL.to_string());
+        Ok(SlackAgent {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            auth_header: format!("Bearer {}", token),
+            channel_id,
+            bot_user_id: Mutex::new(None),
+            last_seen_ts: Mutex::new(None),
+        })
+    }
+
+    // Slack always answers with HTTP 200 and reports errors in the body
+    async fn call<T: DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        let body = response.text().await?;
+        trace!("slack response: {}", body);
+        let status: SlackStatus = serde_json::from_str(&body)?;
+        if !status.ok {
+            return Err(status.error.unwrap_or("unknown error".to_string()).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    async fn say(&self, message: &str, thread_ts: Option<String>) {
+        let request = SlackPostMessage {
+            channel: self.channel_id.clone(),
+            text: to_mrkdwn(message),
+            mrkdwn: true,
+            thread_ts,
+        };
+        let Ok(agent) = request_agent() else {
+            error!("cannot get request agent");
+            return;
+        };
+        let response = match agent
+            .post(format!("{}/chat.postMessage", self.api_url))
+            .header("Authorization", &self.auth_header)
+            .json(&request)
+            .send_measured()
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => {
+                error!("slack post: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = self.call::<SlackStatus>(response).await {
+            error!("slack post: {}", err);
+        }
+    }
+
+    async fn bot_user_id(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut lock = self.bot_user_id.lock().await;
+        if let Some(user_id) = lock.as_ref() {
+            return Ok(user_id.clone());
+        }
+        let response = request_agent()?
+            .post(format!("{}/auth.test", self.api_url))
+            .header("Authorization", &self.auth_header)
+            .send_measured()
+            .await?;
+        let auth: SlackAuthTest = self.call(response).await?;
+        trace!("slack bot user id is {}", auth.user_id);
+        *lock = Some(auth.user_id.clone());
+        Ok(auth.user_id)
+    }
+
+    async fn unread_mentions(&self) -> Result<Vec<SlackMessage>, Box<dyn Error + Send + Sync>> {
+        let mention = format!("<@{}>", self.bot_user_id().await?);
+        let mut lock = self.last_seen_ts.lock().await;
+        let first_read = lock.is_none();
+        let mut messages = Vec::new();
+        let mut cursor = None;
+        for _ in 0..HISTORY_MAX_PAGES {
+            let mut query = vec![("channel", self.channel_id.clone())];
+            match lock.as_ref() {
+                Some(last) => {
+                    query.push(("oldest", last.clone()));
+                    query.push(("limit", HISTORY_PAGE_SIZE.to_string()));
+                }
+                // Only the latest message is needed to know where to start
+                None => query.push(("limit", "1".to_string())),
+            }
+            if let Some(cursor) = cursor.take() {
+                query.push(("cursor", cursor));
+            }
+            let url = reqwest::Url::parse_with_params(
+                &format!("{}/conversations.history", self.api_url),
+                &query,
+            )?;
+            let response = request_agent()?
+                .get(url)
+                .header("Authorization", &self.auth_header)
+                .send_measured()
+                .await?;
+            let history: SlackHistory = self.call(response).await?;
+            messages.extend(history.messages);
+            cursor = history
+                .response_metadata
+                .and_then(|metadata| metadata.next_cursor)
+                .filter(|cursor| !cursor.is_empty());
+            if first_read || !history.has_more || cursor.is_none() {
+                break;
+            }
+        }
+
+        // Slack lists newest messages first
+        messages.sort_by(|a, b| compare_ts(&a.ts, &b.ts));
+        if let Some(m) = messages.last() {
+            *lock = Some(m.ts.clone());
+        } else if first_read {
+            *lock = Some(String::from("0"));
+        }
+        // Don't answer to messages sent before the bot started for the first time
+        if first_read {
+            return Ok(Vec::new());
+        }
+        messages.retain(|m| m.bot_id.is_none() && m.text.contains(&mention));
+        Ok(messages)
+    }
+}
+
+// Timestamps look like 1700000000.123456
+fn compare_ts(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |ts: &str| -> (u64, u64) {
+        let (seconds, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+        (
+            seconds.parse().unwrap_or_default(),
+            micros.parse().unwrap_or_default(),
+        )
+    };
+    parse(a).cmp(&parse(b))
+}
+
+// Slack uses its own markup: <url|text> for links and single * for bold
+fn to_mrkdwn(markdown: &str) -> String {
+    lazy_static! {
+        static ref LINK: Regex = Regex::new(r"\[([^\]]+)\]\(([^)]+)\)").unwrap();
+        static ref BOLD: Regex = Regex::new(r"\*\*([^*]+)\*\*").unwrap();
+    }
+    let text = LINK.replace_all(markdown, "<$2|$1>");
+    BOLD.replace_all(&text, "*$1*").to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SlackStatus {
+    ok: bool,
+    error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SlackAuthTest {
+    user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SlackHistory {
+    messages: Vec<SlackMessage>,
+    #[serde(default)]
+    has_more: bool,
+    response_metadata: Option<SlackResponseMetadata>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SlackResponseMetadata {
+    next_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SlackMessage {
+    ts: String,
+    #[serde(default)]
+    text: String,
+    thread_ts: Option<String>,
+    bot_id: Option<String>,
+}
@@ -0,0 +1,196 @@
+use crate::bot::{ModuleParam, ModuleState};
+use crate::config;
+use async_trait::async_trait;
+use log::{error, info, trace};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Mutex as StdMutex;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "STATE_BACKEND",
+            "Where modules persist their state between restarts: 'json' or 'sqlite'. State is kept in memory only if not set",
+            false,
+        ),
+        ModuleParam::new(
+            "STATE_PATH",
+            "Path of the state file (default: richard.json or richard.sqlite)",
+            false,
+        ),
+    ]
+}
+
+#[async_trait]
+pub trait StateBackend {
+    async fn load(&self, module: &str)
+        -> Result<Option<ModuleState>, Box<dyn Error + Send + Sync>>;
+    async fn save(
+        &self,
+        module: &str,
+        state: &ModuleState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+pub struct StateStore {
+    backend: Box<dyn StateBackend + Send + Sync>,
+    // Last saved state per module, avoid writing when nothing changed
+    saved: Mutex<HashMap<String, ModuleState>>,
+}
+
+impl StateStore {
+    pub fn from_env() -> Result<Option<StateStore>, Box<dyn Error + Send + Sync>> {
+        let Ok(backend_name) = config::var("STATE_BACKEND") else {
+            info!("no state backend configured, module states will not be persisted");
+            return Ok(None);
+        };
+        let path = config::var("STATE_PATH").ok();
+        let backend: Box<dyn StateBackend + Send + Sync> = match backend_name.as_str() {
+            "json" => Box::new(JsonFileBackend::new(
+                path.unwrap_or("richard.json".to_string()),
+            )?),
+            "sqlite" => Box::new(SqliteBackend::new(
+                path.unwrap_or("richard.sqlite".to_string()),
+            )?),
+            other => return Err(format!("unknown state backend '{}'", other).into()),
+        };
+        info!("module states are persisted using {} backend", backend_name);
+        Ok(Some(StateStore {
+            backend,
+            saved: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    pub async fn load(&self, module: &str) -> Option<ModuleState> {
+        match self.backend.load(module).await {
+            Ok(Some(state)) => {
+                trace!("state loaded for module {}", module);
+                let mut lock = self.saved.lock().await;
+                lock.insert(module.to_string(), state.clone());
+                Some(state)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                error!("cannot load state of module {}: {}", module, err);
+                None
+            }
+        }
+    }
+
+    pub async fn save(&self, module: &str, state: ModuleState) {
+        let mut lock = self.saved.lock().await;
+        if lock.get(module) == Some(&state) {
+            return;
+        }
+        trace!("saving state of module {}", module);
+        if let Err(err) = self.backend.save(module, &state).await {
+            error!("cannot save state of module {}: {}", module, err);
+            return;
+        }
+        lock.insert(module.to_string(), state);
+    }
+}
+
+struct JsonFileBackend {
+    path: PathBuf,
+    states: Mutex<HashMap<String, ModuleState>>,
+}
+
+impl JsonFileBackend {
+    fn new(path: String) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let path = PathBuf::from(path);
+        let states = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(Box::new(err)),
+        };
+        Ok(JsonFileBackend {
+            path,
+            states: Mutex::new(states),
+        })
+    }
+}
+
+#[async_trait]
+impl StateBackend for JsonFileBackend {
+    async fn load(
+        &self,
+        module: &str,
+    ) -> Result<Option<ModuleState>, Box<dyn Error + Send + Sync>> {
+        let lock = self.states.lock().await;
+        Ok(lock.get(module).cloned())
+    }
+
+    async fn save(
+        &self,
+        module: &str,
+        state: &ModuleState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut lock = self.states.lock().await;
+        lock.insert(module.to_string(), state.clone());
+        let content = serde_json::to_string_pretty(&*lock)?;
+        // Write to a temporary file first so a crash never leaves a truncated state
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, content).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+struct SqliteBackend {
+    connection: StdMutex<Connection>,
+}
+
+impl SqliteBackend {
+    fn new(path: String) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS module_states (module TEXT PRIMARY KEY, state TEXT NOT NULL)",
+            [],
+        )?;
+        Ok(SqliteBackend {
+            connection: StdMutex::new(connection),
+        })
+    }
+}
+
+#[async_trait]
+impl StateBackend for SqliteBackend {
+    async fn load(
+        &self,
+        module: &str,
+    ) -> Result<Option<ModuleState>, Box<dyn Error + Send + Sync>> {
+        let content: Option<String> = {
+            let connection = self.connection.lock().map_err(|err| err.to_string())?;
+            connection
+                .query_row(
+                    "SELECT state FROM module_states WHERE module = ?1",
+                    params![module],
+                    |row| row.get(0),
+                )
+                .optional()?
+        };
+        match content {
+            Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        module: &str,
+        state: &ModuleState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let content = serde_json::to_string(state)?;
+        let connection = self.connection.lock().map_err(|err| err.to_string())?;
+        connection.execute(
+            "INSERT INTO module_states (module, state) VALUES (?1, ?2) ON CONFLICT(module) DO UPDATE SET state = excluded.state",
+            params![module, content],
+        )?;
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
-    SharedModule,
+    ModuleState, SharedModule,
 };
 use async_trait::async_trait;
 use log::trace;
@@ -18,16 +18,16 @@ pub struct Triggers {
     inner: RwLock<InnerTriggers>,
 }
 
+pub fn params() -> Vec<ModuleParam> {
+    Vec::new()
+}
+
 #[async_trait]
 impl Module for Triggers {
     fn name(&self) -> &'static str {
         "triggers"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        Vec::new()
-    }
-
     async fn module_offering(&self, modules: &[ModuleData]) {
         let trigger_modules = modules
             .iter()
@@ -88,6 +88,12 @@ impl Module for Triggers {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
 }
 
 impl Triggers {
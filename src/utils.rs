@@ -1,6 +1,8 @@
+use std::future::Future;
 use std::{sync::LazyLock, time::Duration};
 
-use reqwest::Client;
+use crate::metrics;
+use reqwest::{Client, RequestBuilder, Response};
 
 static USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 static CLIENT: LazyLock<Client> = LazyLock::new(|| {
@@ -19,3 +21,31 @@ pub fn request_agent() -> Result<Client, reqwest::Error> {
     let client = &*CLIENT;
     Ok(client.clone())
 }
+
+pub trait MeasuredSend {
+    fn send_measured(self) -> impl Future<Output = reqwest::Result<Response>> + Send;
+}
+
+// Same as RequestBuilder::send, also counting requests per host and status code
+impl MeasuredSend for RequestBuilder {
+    async fn send_measured(self) -> reqwest::Result<Response> {
+        let (client, request) = self.build_split();
+        let request = request?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        let result = client.execute(request).await;
+        let status = result
+            .as_ref()
+            .ok()
+            .map(|response| response.status().as_u16());
+        metrics::http_request(&host, status);
+        result
+    }
+}
+
+// Compare secrets without leaking their content through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
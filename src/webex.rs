@@ -1,31 +1,33 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
-use crate::utils::request_agent;
+use crate::config;
+use crate::metrics;
+use crate::utils::{request_agent, MeasuredSend};
 use async_trait::async_trait;
 use log::{error, trace};
 use reqwest::RequestBuilder;
 use serde::Deserialize;
 use serde::Serialize;
-use std::env;
 use std::env::VarError;
 use std::error::Error;
 use tokio::sync::Mutex;
 use tokio::time::Duration;
 
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new("WEBEX_TOKEN", "token provided by webex. See how to create a [controller bot](https://developer.webex.com/docs/bots).", true),
+        ModuleParam::new("WEBEX_ROOM_ID", "webex room id where to speak", true),
+    ]
+}
+
 #[async_trait]
 impl Module for Webex {
     fn name(&self) -> &'static str {
         "webex"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        vec![
-            ModuleParam::new("WEBEX_TOKEN", "token provided by webex. See how to create a [controller bot](https://developer.webex.com/docs/bots).", true),
-            ModuleParam::new("WEBEX_ROOM_ID", "webex room id where to speak", true),
-        ]
-    }
-
     async fn module_offering(&self, _modules: &[ModuleData]) {}
 
     async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
@@ -56,7 +58,9 @@ impl Module for Webex {
 
     async fn read_message(&self) -> Option<Vec<MessageCtx>> {
         let mut unread_messages = Vec::new();
-        let messages = self.agent.unread_messages().await.ok()?;
+        let messages = self.agent.unread_messages().await;
+        metrics::chat_backend_reachable(self.name(), messages.is_ok());
+        let messages = messages.ok()?;
         for message in messages.items {
             unread_messages.push(MessageCtx {
                 content: message.text,
@@ -72,6 +76,22 @@ impl Module for Webex {
     async fn resp_message(&self, parent: MessageCtx, message: Message) {
         self.agent.respond(&message, &parent.id).await;
     }
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let lock = self.agent.last_unread_message_date.lock().await;
+        let last_unread_message_date = lock.as_ref()?;
+        serde_json::to_value(last_unread_message_date).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        match serde_json::from_value::<String>(state) {
+            Ok(date) => {
+                let mut lock = self.agent.last_unread_message_date.lock().await;
+                *lock = Some(date);
+            }
+            Err(err) => error!("cannot load webex state: {}", err),
+        }
+    }
 }
 
 pub struct Webex {
@@ -104,8 +124,8 @@ struct WebexQuery {
 
 impl WebexAgent {
     fn new() -> Result<WebexAgent, VarError> {
-        let webex_token = env::var("WEBEX_TOKEN")?;
-        let room_id = env::var("WEBEX_ROOM_ID")?;
+        let webex_token = config::var("WEBEX_TOKEN")?;
+        let room_id = config::var("WEBEX_ROOM_ID")?;
         Ok(WebexAgent {
             auth_header: format!("Bearer {}", webex_token),
             room_id,
@@ -148,7 +168,7 @@ impl WebexAgent {
             error!("cannot create post request");
             return;
         };
-        match builder.send().await {
+        match builder.send_measured().await {
             Ok(resp) => trace!(
                 "status: {}, content: {:#?}",
                 resp.status(),
@@ -174,7 +194,7 @@ impl WebexAgent {
                 return;
             }
         };
-        if let Err(err) = post.send().await {
+        if let Err(err) = post.send_measured().await {
             error!("webex respond: {:#?}", err)
         }
     }
@@ -184,7 +204,7 @@ impl WebexAgent {
             "https://webexapis.com/v1/messages?roomId={}&mentionedPeople=me",
             self.room_id
         );
-        let body = self.get(url)?.send().await?.text().await?;
+        let body = self.get(url)?.send_measured().await?.text().await?;
         trace!("{}", body);
         let mut res: WebexMessages = serde_json::from_str(body.as_str())?;
 
@@ -0,0 +1,237 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::http_server;
+use crate::utils::constant_time_eq;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::{info, trace, warn};
+use regex::{Captures, Regex};
+use serde_json::Value;
+use sha2::Sha256;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+const DEFAULT_TEMPLATE: &str = "{{body}}";
+const DEFAULT_HMAC_HEADER: &str = "X-Richard-Signature";
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "WEBHOOK_IN_0_PATH",
+            "HTTP path receiving POST requests (e.g. /hooks/ci), can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_IN_0_TEMPLATE",
+            "Message template, {{body}} is the raw body and {{json.a.b}} a field of a JSON body (default: {{body}})",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_IN_0_SECRET",
+            "Shared secret expected in 'Authorization: Bearer <secret>' header",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_IN_0_HMAC_SECRET",
+            "Secret used to check the HMAC-SHA256 signature of the body",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_IN_0_HMAC_HEADER",
+            "Header holding the signature as sha256=<hex> (default: X-Richard-Signature)",
+            false,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for WebhookIn {
+    fn name(&self) -> &'static str {
+        "webhook_in"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        let mut lock = self.inbox.lock().await;
+        if lock.is_empty() {
+            return None;
+        }
+        info!("relaying {} received webhook(s)", lock.len());
+        Some(lock.drain(..).collect())
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities::default()
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(1)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, _messages: &[Message]) {}
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
+}
+
+pub struct WebhookIn {
+    // Messages received by the HTTP server and not yet sent
+    inbox: Arc<Mutex<Vec<Message>>>,
+}
+
+impl WebhookIn {
+    pub fn new() -> Result<Self, String> {
+        let inbox = Arc::new(Mutex::new(Vec::new()));
+        let mut hook_cnt = 0;
+        for i in 0..100 {
+            let Ok(path) = config::var(format!("WEBHOOK_IN_{}_PATH", i)) else {
+                break;
+            };
+            let hook = Arc::new(Hook::new(i, path));
+            if hook.secret.is_none() && hook.hmac_secret.is_none() {
+                return Err(format!(
+                    "WEBHOOK_IN_{}: neither secret nor hmac_secret configured for {}",
+                    i, hook.path
+                ));
+            }
+            let path = hook.path.clone();
+            let inbox = inbox.clone();
+            let handler = post(move |headers: HeaderMap, body: Bytes| async move {
+                hook.receive(&headers, &body, &inbox).await
+            });
+            http_server::route(&path, handler)?;
+            info!("incoming webhook on {} configured", path);
+            hook_cnt += 1;
+        }
+        if hook_cnt == 0 {
+            warn!("webhook_in module enabled but no webhook configured");
+        }
+        Ok(WebhookIn { inbox })
+    }
+}
+
+struct Hook {
+    path: String,
+    template: String,
+    secret: Option<String>,
+    hmac_secret: Option<String>,
+    hmac_header: String,
+}
+
+impl Hook {
+    fn new(index: usize, path: String) -> Hook {
+        Hook {
+            path,
+            template: config::var(format!("WEBHOOK_IN_{}_TEMPLATE", index))
+                .unwrap_or(DEFAULT_TEMPLATE.to_string()),
+            secret: config::var(format!("WEBHOOK_IN_{}_SECRET", index)).ok(),
+            hmac_secret: config::var(format!("WEBHOOK_IN_{}_HMAC_SECRET", index)).ok(),
+            hmac_header: config::var(format!("WEBHOOK_IN_{}_HMAC_HEADER", index))
+                .unwrap_or(DEFAULT_HMAC_HEADER.to_string()),
+        }
+    }
+
+    async fn receive(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        inbox: &Mutex<Vec<Message>>,
+    ) -> StatusCode {
+        if !self.is_authorized(headers, body) {
+            warn!("incoming webhook {}: unauthorized request", self.path);
+            return StatusCode::UNAUTHORIZED;
+        }
+        let message = self.render(&String::from_utf8_lossy(body));
+        if message.trim().is_empty() {
+            trace!("incoming webhook {}: empty message", self.path);
+            return StatusCode::BAD_REQUEST;
+        }
+        trace!("incoming webhook {}: {}", self.path, message);
+        inbox.lock().await.push(message);
+        StatusCode::ACCEPTED
+    }
+
+    // All configured authentication methods must succeed
+    fn is_authorized(&self, headers: &HeaderMap, body: &[u8]) -> bool {
+        if let Some(secret) = self.secret.as_ref() {
+            let authorization = headers
+                .get("Authorization")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            let expected = format!("Bearer {}", secret);
+            if !constant_time_eq(authorization.as_bytes(), expected.as_bytes()) {
+                return false;
+            }
+        }
+        if let Some(hmac_secret) = self.hmac_secret.as_ref() {
+            let signature = headers
+                .get(&self.hmac_header)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("sha256="))
+                .and_then(|value| hex::decode(value).ok());
+            let Some(signature) = signature else {
+                return false;
+            };
+            let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(hmac_secret.as_bytes()) else {
+                return false;
+            };
+            mac.update(body);
+            if mac.verify_slice(&signature).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn render(&self, body: &str) -> Message {
+        lazy_static! {
+            static ref PLACEHOLDER: Regex = Regex::new(r"\{\{\s*([\w.-]+)\s*\}\}").unwrap();
+        }
+        let json = serde_json::from_str::<Value>(body).unwrap_or(Value::Null);
+        PLACEHOLDER
+            .replace_all(&self.template, |captures: &Captures| {
+                let name = &captures[1];
+                if name == "body" {
+                    return body.to_string();
+                }
+                let Some(field) = name.strip_prefix("json.") else {
+                    return captures[0].to_string();
+                };
+                let value =
+                    field
+                        .split('.')
+                        .try_fold(&json, |value, key| match key.parse::<usize>() {
+                            Ok(index) => value.get(index),
+                            Err(_) => value.get(key),
+                        });
+                match value {
+                    Some(Value::String(value)) => value.clone(),
+                    Some(Value::Null) | None => String::new(),
+                    Some(value) => value.to_string(),
+                }
+            })
+            .to_string()
+    }
+}
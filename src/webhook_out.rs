@@ -0,0 +1,242 @@
+use crate::bot::{
+    Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
+};
+use crate::config;
+use crate::utils::{request_agent, MeasuredSend};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use log::{error, info, trace, warn};
+use sha2::Sha256;
+use std::env::VarError;
+use tokio::time::Duration;
+
+const DEFAULT_TEMPLATE: &str = r#"{"text": "{{message}}"}"#;
+const DEFAULT_HMAC_HEADER: &str = "X-Richard-Signature";
+const MESSAGE_PLACEHOLDER: &str = "{{message}}";
+
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "WEBHOOK_0_URL",
+            "URL where to POST bot messages, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_0_TEMPLATE",
+            "Body template, {{message}} is replaced by the message (default: {\"text\": \"{{message}}\"})",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_0_HEADER_0_NAME",
+            "Name of an additional HTTP header, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_0_HEADER_0_VALUE",
+            "Value of an additional HTTP header, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_0_HMAC_SECRET",
+            "Secret used to sign the body with HMAC-SHA256",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBHOOK_0_HMAC_HEADER",
+            "Header holding the signature as sha256=<hex> (default: X-Richard-Signature)",
+            false,
+        ),
+    ]
+}
+
+#[async_trait]
+impl Module for WebhookOut {
+    fn name(&self) -> &'static str {
+        "webhook_out"
+    }
+
+    async fn module_offering(&self, _modules: &[ModuleData]) {}
+
+    async fn run(&self, _variation: usize) -> Option<Vec<Message>> {
+        None
+    }
+
+    fn capabilities(&self) -> ModuleCapabilities {
+        ModuleCapabilities {
+            send_message: true,
+            ..ModuleCapabilities::default()
+        }
+    }
+
+    fn variation_durations(&self) -> Vec<Duration> {
+        vec![Duration::from_secs(3600)]
+    }
+
+    async fn trigger(&self, _message: &str) -> Option<Vec<MessageResponse>> {
+        None
+    }
+
+    async fn send_message(&self, messages: &[Message]) {
+        for message in messages {
+            for webhook in self.webhooks.iter() {
+                webhook.post(message).await;
+            }
+        }
+    }
+
+    async fn read_message(&self) -> Option<Vec<MessageCtx>> {
+        None
+    }
+
+    async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        None
+    }
+
+    async fn load_state(&self, _state: ModuleState) {}
+}
+
+pub struct WebhookOut {
+    webhooks: Vec<Webhook>,
+}
+
+impl WebhookOut {
+    pub fn new() -> Result<Self, VarError> {
+        let mut webhooks = Vec::new();
+        for i in 0..100 {
+            let Ok(url) = config::var(format!("WEBHOOK_{}_URL", i)) else {
+                break;
+            };
+            let webhook = Webhook::new(i, url);
+            info!("outgoing {} configured", webhook.label);
+            webhooks.push(webhook);
+        }
+        if webhooks.is_empty() {
+            warn!("webhook_out module enabled but no webhook configured");
+        }
+        Ok(WebhookOut { webhooks })
+    }
+}
+
+struct Webhook {
+    url: String,
+    // Webhook URLs often hold the credential, only the index and host are logged
+    label: String,
+    template: String,
+    // JSON templates get the message escaped, other templates get it verbatim
+    json: bool,
+    headers: Vec<(String, String)>,
+    hmac_secret: Option<String>,
+    hmac_header: String,
+}
+
+impl Webhook {
+    fn new(index: usize, url: String) -> Webhook {
+        let label = match reqwest::Url::parse(&url) {
+            Ok(parsed) => format!(
+                "webhook {} ({}://{})",
+                index,
+                parsed.scheme(),
+                parsed.host_str().unwrap_or_default()
+            ),
+            Err(_) => format!("webhook {}", index),
+        };
+        let template = config::var(format!("WEBHOOK_{}_TEMPLATE", index))
+            .unwrap_or(DEFAULT_TEMPLATE.to_string());
+        let json = serde_json::from_str::<serde_json::Value>(
+            &template.replace(MESSAGE_PLACEHOLDER, "message"),
+        )
+        .is_ok();
+        if !template.contains(MESSAGE_PLACEHOLDER) {
+            warn!(
+                "{}: template does not contain {}",
+                label, MESSAGE_PLACEHOLDER
+            );
+        }
+        let mut headers = Vec::new();
+        for i in 0..100 {
+            let name = config::var(format!("WEBHOOK_{}_HEADER_{}_NAME", index, i));
+            let value = config::var(format!("WEBHOOK_{}_HEADER_{}_VALUE", index, i));
+            match (name, value) {
+                (Ok(name), Ok(value)) => headers.push((name, value)),
+                _ => break,
+            }
+        }
+        Webhook {
+            url,
+            label,
+            template,
+            json,
+            headers,
+            hmac_secret: config::var(format!("WEBHOOK_{}_HMAC_SECRET", index)).ok(),
+            hmac_header: config::var(format!("WEBHOOK_{}_HMAC_HEADER", index))
+                .unwrap_or(DEFAULT_HMAC_HEADER.to_string()),
+        }
+    }
+
+    fn body(&self, message: &str) -> String {
+        let message = match self.json {
+            true => {
+                let escaped = serde_json::Value::String(message.to_string()).to_string();
+                escaped[1..escaped.len() - 1].to_string()
+            }
+            false => message.to_string(),
+        };
+        self.template.replace(MESSAGE_PLACEHOLDER, &message)
+    }
+
+    fn signature(&self, body: &str) -> Option<String> {
+        let secret = self.hmac_secret.as_ref()?;
+        let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+            Ok(mac) => mac,
+            Err(err) => {
+                error!("{}: cannot init hmac: {}", self.label, err);
+                return None;
+            }
+        };
+        mac.update(body.as_bytes());
+        Some(format!(
+            "sha256={}",
+            hex::encode(mac.finalize().into_bytes())
+        ))
+    }
+
+    async fn post(&self, message: &str) {
+        let body = self.body(message);
+        let Ok(agent) = request_agent() else {
+            error!("cannot get request agent");
+            return;
+        };
+        let content_type = match self.json {
+            true => "application/json",
+            false => "text/plain; charset=utf-8",
+        };
+        let mut builder = agent.post(&self.url);
+        // Custom headers may override the content type, e.g. for form encoded templates
+        if !self
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        {
+            builder = builder.header("Content-Type", content_type);
+        }
+        if let Some(signature) = self.signature(&body) {
+            builder = builder.header(&self.hmac_header, signature);
+        }
+        for (name, value) in self.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        match builder.body(body).send_measured().await {
+            Ok(resp) if !resp.status().is_success() => error!(
+                "{}: status: {}, content: {:#?}",
+                self.label,
+                resp.status(),
+                resp.text().await
+            ),
+            Ok(resp) => trace!("{}: status: {}", self.label, resp.status()),
+            Err(err) => error!("{}: {}", self.label, err.without_url()),
+        };
+    }
+}
@@ -1,35 +1,37 @@
 use crate::bot::{
     Message, MessageCtx, MessageResponse, Module, ModuleCapabilities, ModuleData, ModuleParam,
+    ModuleState,
 };
-use crate::utils::request_agent;
+use crate::config;
+use crate::utils::{request_agent, MeasuredSend};
 use async_trait::async_trait;
 use log::{error, info, warn};
-use std::env;
+use std::collections::HashMap;
 use std::env::VarError;
 use tokio::sync::Mutex;
 use tokio::time::Duration;
 
+pub fn params() -> Vec<ModuleParam> {
+    vec![
+        ModuleParam::new(
+            "WEBPAGES_0_NAME",
+            "Webpage name, can be multiple (0..)",
+            false,
+        ),
+        ModuleParam::new(
+            "WEBPAGES_0_URL",
+            "Webpage URL, can be multiple (0..)",
+            false,
+        ),
+    ]
+}
+
 #[async_trait]
 impl Module for Webpages {
     fn name(&self) -> &'static str {
         "webpages"
     }
 
-    fn params(&self) -> Vec<ModuleParam> {
-        vec![
-            ModuleParam::new(
-                "WEBPAGES_0_NAME",
-                "Webpage name, can be multiple (0..)",
-                false,
-            ),
-            ModuleParam::new(
-                "WEBPAGES_0_URL",
-                "Webpage URL, can be multiple (0..)",
-                false,
-            ),
-        ]
-    }
-
     fn capabilities(&self) -> ModuleCapabilities {
         ModuleCapabilities::default()
     }
@@ -65,6 +67,30 @@ impl Module for Webpages {
     }
 
     async fn resp_message(&self, _parent: MessageCtx, _message: Message) {}
+
+    async fn save_state(&self) -> Option<ModuleState> {
+        let mut contents = HashMap::new();
+        for page in self.pages.iter() {
+            if let Some(content) = page.content.lock().await.clone() {
+                contents.insert(page.name.clone(), content);
+            }
+        }
+        serde_json::to_value(contents).ok()
+    }
+
+    async fn load_state(&self, state: ModuleState) {
+        let mut contents: HashMap<String, String> = match serde_json::from_value(state) {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!("cannot load webpages state: {}", err);
+                return;
+            }
+        };
+        for page in self.pages.iter() {
+            let mut lock = page.content.lock().await;
+            *lock = contents.remove(&page.name);
+        }
+    }
 }
 
 #[derive(Default)]
@@ -76,8 +102,8 @@ impl Webpages {
     pub fn new() -> Result<Self, VarError> {
         let mut webpages = Webpages::default();
         for i in 0..100 {
-            let name = env::var(format!("WEBPAGES_{}_NAME", i));
-            let url = env::var(format!("WEBPAGES_{}_URL", i));
+            let name = config::var(format!("WEBPAGES_{}_NAME", i));
+            let url = config::var(format!("WEBPAGES_{}_URL", i));
             match (name, url) {
                 (Ok(name), Ok(url)) => {
                     info!("webpage configured: '{}' on url '{}'", name, url);
@@ -117,7 +143,7 @@ impl Webpage {
                 return false;
             }
         };
-        let result = match agent.get(self.url.clone()).send().await {
+        let result = match agent.get(self.url.clone()).send_measured().await {
             Ok(res) => res,
             Err(err) => {
                 error!("{:#?}", err);